@@ -0,0 +1,201 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// Candle granularities accepted by the OANDA v3 candles endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Granularity {
+    S5,
+    S10,
+    S15,
+    S30,
+    M1,
+    M2,
+    M4,
+    M5,
+    M10,
+    M15,
+    M30,
+    H1,
+    H2,
+    H3,
+    H4,
+    H6,
+    H8,
+    H12,
+    D,
+    W,
+    M,
+}
+
+impl Granularity {
+    /// Every granularity, shortest first.
+    pub const ALL: [Granularity; 21] = [
+        Granularity::S5, Granularity::S10, Granularity::S15, Granularity::S30,
+        Granularity::M1, Granularity::M2, Granularity::M4, Granularity::M5, Granularity::M10, Granularity::M15, Granularity::M30,
+        Granularity::H1, Granularity::H2, Granularity::H3, Granularity::H4, Granularity::H6, Granularity::H8, Granularity::H12,
+        Granularity::D, Granularity::W, Granularity::M,
+    ];
+
+    /// Name as accepted by the OANDA candles endpoint, also used as the table suffix.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::S5 => "S5",
+            Granularity::S10 => "S10",
+            Granularity::S15 => "S15",
+            Granularity::S30 => "S30",
+            Granularity::M1 => "M1",
+            Granularity::M2 => "M2",
+            Granularity::M4 => "M4",
+            Granularity::M5 => "M5",
+            Granularity::M10 => "M10",
+            Granularity::M15 => "M15",
+            Granularity::M30 => "M30",
+            Granularity::H1 => "H1",
+            Granularity::H2 => "H2",
+            Granularity::H3 => "H3",
+            Granularity::H4 => "H4",
+            Granularity::H6 => "H6",
+            Granularity::H8 => "H8",
+            Granularity::H12 => "H12",
+            Granularity::D => "D",
+            Granularity::W => "W",
+            Granularity::M => "M",
+        }
+    }
+
+    /// Length of one candle, `None` for months. Days and weeks are nominal, DST changes
+    /// make some an hour shorter or longer.
+    pub fn duration(&self) -> Option<TimeDelta> {
+        Some(match self {
+            Granularity::S5 => TimeDelta::seconds(5),
+            Granularity::S10 => TimeDelta::seconds(10),
+            Granularity::S15 => TimeDelta::seconds(15),
+            Granularity::S30 => TimeDelta::seconds(30),
+            Granularity::M1 => TimeDelta::minutes(1),
+            Granularity::M2 => TimeDelta::minutes(2),
+            Granularity::M4 => TimeDelta::minutes(4),
+            Granularity::M5 => TimeDelta::minutes(5),
+            Granularity::M10 => TimeDelta::minutes(10),
+            Granularity::M15 => TimeDelta::minutes(15),
+            Granularity::M30 => TimeDelta::minutes(30),
+            Granularity::H1 => TimeDelta::hours(1),
+            Granularity::H2 => TimeDelta::hours(2),
+            Granularity::H3 => TimeDelta::hours(3),
+            Granularity::H4 => TimeDelta::hours(4),
+            Granularity::H6 => TimeDelta::hours(6),
+            Granularity::H8 => TimeDelta::hours(8),
+            Granularity::H12 => TimeDelta::hours(12),
+            Granularity::D => TimeDelta::days(1),
+            Granularity::W => TimeDelta::weeks(1),
+            Granularity::M => return None,
+        })
+    }
+
+    /// Inverse of [`Granularity::as_str`].
+    pub fn parse(s: &str) -> Option<Granularity> {
+        Granularity::ALL.iter().copied().find(|g| g.as_str() == s)
+    }
+}
+
+/// Which prices of a candle to request and store: mid, bid or ask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PriceComponent {
+    M,
+    B,
+    A,
+}
+
+impl PriceComponent {
+    pub const ALL: [PriceComponent; 3] = [PriceComponent::M, PriceComponent::B, PriceComponent::A];
+
+    /// Letter used in the `price` query parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceComponent::M => "M",
+            PriceComponent::B => "B",
+            PriceComponent::A => "A",
+        }
+    }
+
+    /// `mid`, `bid` or `ask`, as used in exports and the normalized `candles` table.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PriceComponent::M => "mid",
+            PriceComponent::B => "bid",
+            PriceComponent::A => "ask",
+        }
+    }
+
+    /// Inverse of [`PriceComponent::name`].
+    pub fn from_name(name: &str) -> Option<PriceComponent> {
+        PriceComponent::ALL.iter().copied().find(|c| c.name() == name)
+    }
+}
+
+/// One candle as returned by OANDA. Only the requested price components are present.
+#[derive(Debug, Deserialize)]
+pub struct Candle {
+    /// Open time of the candle.
+    pub time: DateTime<Utc>,
+    /// False for the candle that is still forming.
+    pub complete: bool,
+    pub volume: f64,
+    pub mid: Option<Ohlc>,
+    pub bid: Option<Ohlc>,
+    pub ask: Option<Ohlc>,
+}
+
+impl Candle {
+    /// Prices of the given component, if it was requested.
+    pub fn ohlc(&self, component: PriceComponent) -> Option<&Ohlc> {
+        match component {
+            PriceComponent::M => self.mid.as_ref(),
+            PriceComponent::B => self.bid.as_ref(),
+            PriceComponent::A => self.ask.as_ref(),
+        }
+    }
+}
+
+/// Open, high, low and close prices, exactly as the decimal strings OANDA sends.
+#[derive(Debug, Deserialize)]
+pub struct Ohlc {
+    pub o: Decimal,
+    pub h: Decimal,
+    pub l: Decimal,
+    pub c: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_parse_back() {
+        for granularity in Granularity::ALL {
+            assert_eq!(Granularity::parse(granularity.as_str()), Some(granularity));
+        }
+        assert!(Granularity::ALL.is_sorted());
+        for component in PriceComponent::ALL {
+            assert_eq!(PriceComponent::from_name(component.name()), Some(component));
+        }
+    }
+
+    #[test]
+    fn prices_deserialize_exactly() {
+        let candle: Candle = serde_json::from_str(
+            r#"{"time": "2026-10-14T10:00:00.000000000Z", "complete": true, "volume": 42,
+                "mid": {"o": "1.08545", "h": "1.08610", "l": "1.08500", "c": "1.085905"}}"#,
+        )
+        .unwrap();
+        let mid = candle.ohlc(PriceComponent::M).unwrap();
+        assert_eq!(mid.o, Decimal::new(108_545, 5));
+        assert_eq!(mid.h.to_string(), "1.08610");
+        assert_eq!(mid.c, Decimal::new(1_085_905, 6));
+        assert!(candle.ohlc(PriceComponent::B).is_none());
+
+        assert!(serde_json::from_str::<Ohlc>(r#"{"o": "n/a", "h": "1", "l": "1", "c": "1"}"#).is_err());
+    }
+}
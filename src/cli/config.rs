@@ -0,0 +1,427 @@
+use super::{
+    parse_daily_alignment_rule, parse_retention_rule, parse_timezone_rule, parse_weekday, rule_for, GlobalArgs, GranularityRule,
+    PriceFormatArg, RetentionRule, SyncArgs,
+};
+use chrono::Weekday;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use oandaohlc::{Alignment, Error, Granularity, Instrument, MatchMode, PriceComponent, Result, Retention, Storage, Whitelist};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Read from the working directory when --config is not given, if it exists.
+const DEFAULT_CONFIG: &str = "oandaohlc.toml";
+const DEFAULT_DB: &str = "oanda.db";
+
+/// Instruments synced when neither --tickers nor the config file select any.
+const DEFAULT_TICKERS: &[&str] = &[
+    "natgas_usd", "xau_usd", "eur_usd", "de30_eur", "xcu_usd", "xag_usd",
+    "sugar_usd", "wtico_usd", "wheat_usd", "corn_usd", "spx500_usd",
+    "jp225_usd", "cn50_usd", "eu50_eur", "fr40_eur", "xau_xag",
+];
+
+/// Contents of the config file. Every series setting can be given at the top level,
+/// per group and per instrument; the most specific one wins, and CLI flags beat them all.
+///
+/// ```toml
+/// db = "oanda.db"
+/// price_format = "scaled"
+/// granularity = ["D", "W", "M"]
+///
+/// [[group]]
+/// name = "metals"
+/// match = "glob"
+/// instruments = ["xau_*", "xag_usd"]
+/// granularity = ["H1", "D"]
+/// retention = ["H1=90d"]
+///
+/// [instrument.eur_usd]
+/// price = ["M", "B", "A"]
+/// live = true
+/// daily_alignment = ["D=0"]
+/// alignment_timezone = ["UTC"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    db: Option<String>,
+    #[serde(default, deserialize_with = "value_enum")]
+    price_format: Option<PriceFormatArg>,
+    #[serde(flatten)]
+    defaults: SeriesConfig,
+    #[serde(default, rename = "group")]
+    groups: Vec<GroupConfig>,
+    #[serde(default, rename = "instrument")]
+    instruments: BTreeMap<String, SeriesConfig>,
+}
+
+/// Instruments synced with the same settings. An instrument in several groups takes
+/// the settings of the first one.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupConfig {
+    name: String,
+    instruments: Vec<String>,
+    #[serde(default, rename = "match", deserialize_with = "value_enum")]
+    match_mode: Option<MatchMode>,
+    #[serde(flatten)]
+    series: SeriesConfig,
+}
+
+/// Series settings where `None` means inherit from the enclosing level.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeriesConfig {
+    #[serde(default, deserialize_with = "value_enums")]
+    granularity: Option<Vec<Granularity>>,
+    #[serde(default, deserialize_with = "value_enums")]
+    price: Option<Vec<PriceComponent>>,
+    #[serde(default, deserialize_with = "retention_rules")]
+    retention: Option<Vec<RetentionRule>>,
+    live: Option<bool>,
+    #[serde(default, deserialize_with = "daily_alignment_rules")]
+    daily_alignment: Option<Vec<GranularityRule<u32>>>,
+    #[serde(default, deserialize_with = "timezone_rules")]
+    alignment_timezone: Option<Vec<GranularityRule<Tz>>>,
+    #[serde(default, deserialize_with = "weekday")]
+    weekly_alignment: Option<Weekday>,
+}
+
+impl SeriesConfig {
+    /// `self` with unset values taken from `base`.
+    fn or(&self, base: &SeriesConfig) -> SeriesConfig {
+        SeriesConfig {
+            granularity: self.granularity.clone().or_else(|| base.granularity.clone()),
+            price: self.price.clone().or_else(|| base.price.clone()),
+            retention: self.retention.clone().or_else(|| base.retention.clone()),
+            live: self.live.or(base.live),
+            daily_alignment: self.daily_alignment.clone().or_else(|| base.daily_alignment.clone()),
+            alignment_timezone: self.alignment_timezone.clone().or_else(|| base.alignment_timezone.clone()),
+            weekly_alignment: self.weekly_alignment.or(base.weekly_alignment),
+        }
+    }
+}
+
+/// What to sync for one instrument once the config file and CLI flags are merged.
+#[derive(Debug, Clone)]
+pub struct SeriesSettings {
+    pub granularities: Vec<Granularity>,
+    pub components: Vec<PriceComponent>,
+    retention: Vec<RetentionRule>,
+    pub live: bool,
+    daily_alignment: Vec<GranularityRule<u32>>,
+    alignment_timezone: Vec<GranularityRule<Tz>>,
+    weekly_alignment: Option<Weekday>,
+}
+
+impl SeriesSettings {
+    pub fn retention(&self, granularity: Granularity) -> Retention {
+        rule_for(&self.retention, granularity).unwrap_or(Retention::All)
+    }
+
+    pub fn alignment(&self, granularity: Granularity) -> Result<Alignment> {
+        let default = Alignment::default();
+        let alignment = Alignment::new(
+            rule_for(&self.daily_alignment, granularity).unwrap_or(default.daily_hour()),
+            rule_for(&self.alignment_timezone, granularity).unwrap_or(default.timezone()),
+            self.weekly_alignment.unwrap_or(default.weekly()),
+        )?;
+        Ok(alignment.for_granularity(granularity))
+    }
+}
+
+impl Config {
+    /// Loads `path`, or `oandaohlc.toml` if no path is given and the file exists.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+            None => return Ok(Config::default()),
+        };
+
+        let text = fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("cannot read {}: {}", path.display(), e)))?;
+        toml::from_str(&text).map_err(|e| Error::Config(format!("invalid config {}: {}", path.display(), e)))
+    }
+
+    /// Database path from --db, the config file or the default, in that order.
+    pub fn db(&self, global: &GlobalArgs) -> String {
+        global.db.clone()
+            .or_else(|| self.db.clone())
+            .unwrap_or_else(|| DEFAULT_DB.to_string())
+    }
+
+    /// Opens the database, choosing the price format from --price-format or the config
+    /// file if either sets one.
+    pub fn open_storage(&self, global: &GlobalArgs) -> Result<Storage> {
+        let mut storage = Storage::open(self.db(global))?;
+        if let Some(format) = global.price_format.or(self.price_format) {
+            storage.set_price_format(format.into())?;
+        }
+        Ok(storage)
+    }
+
+    /// Opens the database like [`Config::open_storage`], but without writing to it.
+    pub fn open_storage_read_only(&self, global: &GlobalArgs) -> Result<Storage> {
+        let mut storage = Storage::open_read_only(self.db(global))?;
+        if let Some(format) = global.price_format.or(self.price_format) {
+            storage.set_price_format(format.into())?;
+        }
+        Ok(storage)
+    }
+
+    /// Picks the instruments to sync from what the account offers and resolves their
+    /// settings. --tickers replaces the config file's selection and --group narrows it;
+    /// without either, the config's groups and instrument sections are synced, falling
+    /// back to the built-in list when it has none.
+    pub fn select<'a>(&self, instruments: &'a [Instrument], args: &SyncArgs) -> Result<Vec<(&'a Instrument, SeriesSettings)>> {
+        let groups = self.groups.iter()
+            .map(|g| Ok((g, Whitelist::new(&g.instruments, g.match_mode.unwrap_or_default())?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let unknown: Vec<&String> = args.group.iter().filter(|name| !self.groups.iter().any(|g| &g.name == *name)).collect();
+        if let Some(name) = unknown.first() {
+            return Err(Error::Config(format!("no group `{}` in the config file", name)));
+        }
+
+        let selected: Vec<&Instrument> = if let Some(tickers) = &args.tickers {
+            let tickers: Vec<&str> = tickers.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+            Whitelist::new(&tickers, args.match_mode)?.select(instruments)?
+        } else if !args.group.is_empty() {
+            let mut selected = Vec::new();
+            for (_, whitelist) in groups.iter().filter(|(g, _)| args.group.contains(&g.name)) {
+                selected.extend(whitelist.select(instruments)?);
+            }
+            dedup_in_account_order(instruments, selected)
+        } else if !self.groups.is_empty() || !self.instruments.is_empty() {
+            let mut selected = Vec::new();
+            for (_, whitelist) in &groups {
+                selected.extend(whitelist.select(instruments)?);
+            }
+            let names: Vec<&String> = self.instruments.keys().collect();
+            selected.extend(Whitelist::new(&names, MatchMode::Exact)?.select(instruments)?);
+            dedup_in_account_order(instruments, selected)
+        } else {
+            // The built-in list is a best effort, as not every account offers all of it.
+            let whitelist = Whitelist::new(DEFAULT_TICKERS, MatchMode::Exact)?;
+            instruments.iter().filter(|i| whitelist.matches(&i.name)).collect()
+        };
+
+        let flags = SeriesConfig {
+            granularity: Some(args.granularity.clone()).filter(|g| !g.is_empty()),
+            price: Some(args.price.clone()).filter(|p| !p.is_empty()),
+            retention: Some(args.retention.clone()).filter(|r| !r.is_empty()),
+            live: args.live.then_some(true),
+            daily_alignment: Some(args.daily_alignment.clone()).filter(|r| !r.is_empty()),
+            alignment_timezone: Some(args.alignment_timezone.clone()).filter(|r| !r.is_empty()),
+            weekly_alignment: args.weekly_alignment,
+        };
+        let builtin = SeriesConfig {
+            granularity: Some(vec![Granularity::D, Granularity::W, Granularity::M]),
+            price: Some(vec![PriceComponent::M]),
+            retention: Some(Vec::new()),
+            live: Some(false),
+            daily_alignment: Some(Vec::new()),
+            alignment_timezone: Some(Vec::new()),
+            weekly_alignment: None,
+        };
+
+        Ok(selected.into_iter()
+            .map(|inst| {
+                let group = groups.iter()
+                    .find(|(_, whitelist)| whitelist.matches(&inst.name))
+                    .map(|(g, _)| g.series.clone())
+                    .unwrap_or_default();
+                let section = self.instruments.iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&inst.name))
+                    .map(|(_, section)| section.clone())
+                    .unwrap_or_default();
+                let merged = flags.or(&section).or(&group).or(&self.defaults).or(&builtin);
+
+                let mut granularities = merged.granularity.unwrap_or_default();
+                granularities.sort();
+                granularities.dedup();
+                let mut components = merged.price.unwrap_or_default();
+                components.sort();
+                components.dedup();
+                (inst, SeriesSettings {
+                    granularities,
+                    components,
+                    retention: merged.retention.unwrap_or_default(),
+                    live: merged.live.unwrap_or_default(),
+                    daily_alignment: merged.daily_alignment.unwrap_or_default(),
+                    alignment_timezone: merged.alignment_timezone.unwrap_or_default(),
+                    weekly_alignment: merged.weekly_alignment,
+                })
+            })
+            .collect())
+    }
+}
+
+fn dedup_in_account_order<'a>(instruments: &'a [Instrument], selected: Vec<&Instrument>) -> Vec<&'a Instrument> {
+    instruments.iter().filter(|i| selected.iter().any(|s| s.name == i.name)).collect()
+}
+
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(deserializer: D) -> Result<Option<T>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| T::from_str(&name, true).map_err(de::Error::custom))
+        .transpose()
+}
+
+fn value_enums<'de, D: Deserializer<'de>, T: ValueEnum>(deserializer: D) -> Result<Option<Vec<T>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|names| names.iter().map(|name| T::from_str(name, true).map_err(de::Error::custom)).collect())
+        .transpose()
+}
+
+fn rules<'de, D: Deserializer<'de>, T>(
+    deserializer: D,
+    parse: fn(&str) -> Result<GranularityRule<T>, String>,
+) -> Result<Option<Vec<GranularityRule<T>>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|rules| rules.iter().map(|rule| parse(rule).map_err(de::Error::custom)).collect())
+        .transpose()
+}
+
+fn retention_rules<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<RetentionRule>>, D::Error> {
+    rules(deserializer, parse_retention_rule)
+}
+
+fn daily_alignment_rules<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<GranularityRule<u32>>>, D::Error> {
+    rules(deserializer, parse_daily_alignment_rule)
+}
+
+fn timezone_rules<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<GranularityRule<Tz>>>, D::Error> {
+    rules(deserializer, parse_timezone_rule)
+}
+
+fn weekday<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Weekday>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|day| parse_weekday(&day).map_err(de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+
+    const CONFIG: &str = r#"
+        granularity = ["W"]
+        price = ["B"]
+        daily_alignment = ["D=0"]
+
+        [[group]]
+        name = "metals"
+        match = "glob"
+        instruments = ["xa?_*"]
+        granularity = ["H1"]
+        live = true
+
+        [[group]]
+        name = "gold"
+        instruments = ["xau_usd"]
+        granularity = ["H4"]
+
+        [instrument.xau_usd]
+        price = ["A"]
+
+        [instrument.eur_usd]
+    "#;
+
+    fn account() -> Vec<Instrument> {
+        ["XAU_USD", "EUR_USD", "XAG_USD", "USD_JPY"].into_iter()
+            .map(|name| {
+                serde_json::from_value(serde_json::json!({
+                    "name": name, "type": "CURRENCY", "displayName": name, "pipLocation": -4, "displayPrecision": 5,
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn select(config: &str, flags: &[&str]) -> Result<Vec<(String, SeriesSettings)>> {
+        let config: Config = toml::from_str(config).unwrap();
+        let args = Cli::try_parse_args_from(["oandaohlc"].iter().chain(flags)).unwrap().sync;
+        let instruments = account();
+        Ok(config.select(&instruments, &args)?
+            .into_iter()
+            .map(|(inst, settings)| (inst.name.clone(), settings))
+            .collect())
+    }
+
+    #[test]
+    fn settings_merge_from_flags_down_to_the_built_in_ones() {
+        let selected = select(CONFIG, &[]).unwrap();
+        let names: Vec<&str> = selected.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["XAU_USD", "EUR_USD", "XAG_USD"]);
+
+        let (_, xau) = &selected[0];
+        // The instrument section beats the group, and the first matching group wins.
+        assert_eq!(xau.granularities, [Granularity::H1]);
+        assert_eq!(xau.components, [PriceComponent::A]);
+        assert!(xau.live);
+        let (_, eur) = &selected[1];
+        assert_eq!(eur.granularities, [Granularity::W]);
+        assert_eq!(eur.components, [PriceComponent::B]);
+        assert!(!eur.live);
+        assert_eq!(eur.alignment(Granularity::D).unwrap().daily_hour(), 0);
+        assert_eq!(eur.alignment(Granularity::W).unwrap().daily_hour(), 17);
+        assert!(matches!(eur.retention(Granularity::W), Retention::All));
+        let (_, xag) = &selected[2];
+        assert_eq!(xag.components, [PriceComponent::B]);
+
+        for (_, settings) in select(CONFIG, &["-g", "D", "M", "-p", "M", "--daily-alignment", "5"]).unwrap() {
+            assert_eq!(settings.granularities, [Granularity::D, Granularity::M]);
+            assert_eq!(settings.components, [PriceComponent::M]);
+            assert_eq!(settings.alignment(Granularity::D).unwrap().daily_hour(), 5);
+        }
+        // A bare -g asks for the built-in granularities, as it did before the config file.
+        for (_, settings) in select(CONFIG, &["-g"]).unwrap() {
+            assert_eq!(settings.granularities, [Granularity::D, Granularity::W, Granularity::M]);
+        }
+    }
+
+    #[test]
+    fn without_a_config_the_built_in_settings_apply() {
+        let selected = select("", &[]).unwrap();
+        let names: Vec<&str> = selected.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["XAU_USD", "EUR_USD", "XAG_USD"]);
+        for (_, settings) in selected {
+            assert_eq!(settings.granularities, [Granularity::D, Granularity::W, Granularity::M]);
+            assert_eq!(settings.components, [PriceComponent::M]);
+            assert!(!settings.live);
+            assert_eq!(settings.alignment(Granularity::D).unwrap(), Alignment::default());
+        }
+    }
+
+    #[test]
+    fn groups_narrow_the_selection() {
+        let selected = select(CONFIG, &["--group", "gold"]).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0, "XAU_USD");
+        // Selected through the second group, but still set up by the first.
+        assert_eq!(selected[0].1.granularities, [Granularity::H1]);
+
+        assert!(matches!(select(CONFIG, &["--group", "energy"]), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        assert!(toml::from_str::<Config>(r#"granularities = ["D"]"#).is_err());
+        assert!(toml::from_str::<Config>("[[group]]\nname = \"fx\"\ninstruments = [\"eur_usd\"]\nretension = [\"90d\"]").is_err());
+        assert!(toml::from_str::<Config>("[instrument.eur_usd]\nlve = true").is_err());
+        assert!(toml::from_str::<Config>(CONFIG).is_ok());
+    }
+
+    #[test]
+    fn built_in_tickers_are_listed_once() {
+        let mut tickers = DEFAULT_TICKERS.to_vec();
+        tickers.sort();
+        tickers.dedup();
+        assert_eq!(tickers.len(), DEFAULT_TICKERS.len());
+    }
+}
@@ -0,0 +1,43 @@
+use super::config::Config;
+use super::{ExportArgs, GlobalArgs};
+use oandaohlc::{CandleWriter, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// Writes the selected series to one file each, or to a single long-format file.
+pub fn export(global: &GlobalArgs, args: &ExportArgs) -> Result<()> {
+    let config = Config::load(global.config.as_deref())?;
+    let storage = config.open_storage_read_only(global)?;
+
+    let selected = args.series.select(&storage)?;
+
+    let extension = args.format.extension();
+    if args.long {
+        let path = args.output.clone().unwrap_or_else(|| PathBuf::from(format!("candles.{}", extension)));
+        let mut writer = CandleWriter::create(&path, args.format, true)?;
+        let mut total = 0;
+        for series in &selected {
+            let candles = storage.candles(series, args.from, args.to)?;
+            writer.write(series, &candles)?;
+            total += candles.len();
+        }
+        writer.finish()?;
+        println!("Exported {} candles from {} series to {}", total, selected.len(), path.display());
+    } else {
+        let dir = args.output.clone().unwrap_or_else(|| PathBuf::from("export"));
+        fs::create_dir_all(&dir)?;
+        for series in &selected {
+            let candles = storage.candles(series, args.from, args.to)?;
+            if candles.is_empty() {
+                continue;
+            }
+            let path = dir.join(format!("{}.{}", series.name(), extension));
+            let mut writer = CandleWriter::create(&path, args.format, false)?;
+            writer.write(series, &candles)?;
+            writer.finish()?;
+            println!("Exported {} candles to {}", candles.len(), path.display());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use super::output::print_output;
+use super::{connect, GlobalArgs, ListInstrumentsArgs};
+use oandaohlc::{Instrument, Result};
+
+/// Prints the account's instruments, optionally filtered by type and quote currency.
+pub async fn list_instruments(global: &GlobalArgs, args: &ListInstrumentsArgs) -> Result<()> {
+    let (client, account_id) = connect(global)?;
+    let mut instruments: Vec<Instrument> = client.instruments(&account_id).await?
+        .into_iter()
+        .filter(|i| args.instrument_type.is_empty() || args.instrument_type.iter().any(|t| t.eq_ignore_ascii_case(&i.instrument_type)))
+        .filter(|i| {
+            let quote = i.name.rsplit('_').next().unwrap_or_default();
+            args.quote.is_empty() || args.quote.iter().any(|q| q.eq_ignore_ascii_case(quote))
+        })
+        .collect();
+    instruments.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let headers = ["name", "type", "display_name", "pip_location", "display_precision", "margin_rate", "minimum_trade_size", "maximum_order_units"];
+    let rows: Vec<Vec<String>> = instruments.iter()
+        .map(|i| vec![
+            i.name.clone(),
+            i.instrument_type.clone(),
+            i.display_name.clone(),
+            i.pip_location.to_string(),
+            i.display_precision.to_string(),
+            i.margin_rate.clone(),
+            i.minimum_trade_size.clone(),
+            i.maximum_order_units.clone(),
+        ])
+        .collect();
+
+    print_output(args.format, &headers, &rows, &instruments)
+}
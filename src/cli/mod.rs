@@ -0,0 +1,511 @@
+pub mod config;
+pub mod export;
+pub mod instruments;
+pub mod normalize;
+pub mod output;
+pub mod resample;
+pub mod status;
+pub mod sync;
+pub mod verify;
+pub mod watch;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use oandaohlc::{
+    Environment, Error, ExportFormat, Granularity, MatchMode, OandaClient, PriceComponent, PriceFormat, Result, Retention, ResampledKey, SeriesKey, Storage,
+    Timeframe, Whitelist,
+};
+use output::OutputFormat;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Options of the default `sync` command
+    #[command(flatten)]
+    pub sync: SyncArgs,
+}
+
+impl Cli {
+    /// Parses the command line, or prints the error and exits.
+    pub fn parse_args() -> Cli {
+        Cli::try_parse_args_from(env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Parses `args`, refusing options of the default `sync` command given before a
+    /// subcommand, which would otherwise be ignored.
+    pub fn try_parse_args_from(args: impl IntoIterator<Item = impl Into<OsString> + Clone>) -> Result<Cli, clap::Error> {
+        let mut command = Cli::command();
+        let matches = command.try_get_matches_from_mut(args)?;
+        if let Some((name, _)) = matches.subcommand() {
+            let sync = <SyncArgs as clap::Args>::augment_args(clap::Command::new("sync"));
+            let misplaced = sync.get_arguments()
+                .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine));
+            if let Some(arg) = misplaced {
+                let flag = arg.get_long().expect("sync options are long flags");
+                return Err(command.error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    format!("--{} belongs to the default sync command, pass it after `{}` instead", flag, name),
+                ));
+            }
+        }
+        Cli::from_arg_matches(&matches)
+    }
+}
+
+/// [`PriceFormat`] as --price-format and the config file name it.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PriceFormatArg {
+    /// REAL floats, the default
+    Real,
+    /// INTEGER counts of the smallest price step, recorded per instrument
+    Scaled,
+    /// TEXT, the exact decimal OANDA sent
+    Text,
+}
+
+impl From<PriceFormatArg> for PriceFormat {
+    fn from(format: PriceFormatArg) -> Self {
+        match format {
+            PriceFormatArg::Real => PriceFormat::Real,
+            PriceFormatArg::Scaled => PriceFormat::Scaled,
+            PriceFormatArg::Text => PriceFormat::Text,
+        }
+    }
+}
+
+/// Options shared by every command.
+#[derive(clap::Args, Debug)]
+pub struct GlobalArgs {
+    /// Config file with instrument groups and per-instrument settings [default: oandaohlc.toml if present]
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Database name (overrides `db` in the config file) [default: oanda.db]
+    #[arg(short, long, global = true)]
+    pub db: Option<String>,
+
+    /// How a new database stores prices: REAL floats, INTEGER counts of the smallest price step or exact decimal TEXT (overrides `price_format` in the config file) [default: real]
+    #[arg(long, global = true, value_enum, ignore_case = true)]
+    pub price_format: Option<PriceFormatArg>,
+
+    /// OANDA environment (overrides OANDA_ENVIRONMENT env variable) [default: live]
+    #[arg(short, long, global = true, value_enum, ignore_case = true)]
+    pub environment: Option<Environment>,
+
+    /// Custom API base URL, takes precedence over --environment (overrides OANDA_BASE_URL env variable)
+    #[arg(long, global = true)]
+    pub base_url: Option<String>,
+
+    /// OANDA Account ID (overrides env variable)
+    #[arg(long, global = true)]
+    pub oanda_account_id: Option<String>,
+
+    /// OANDA Access Token (overrides env variable)
+    #[arg(long, global = true)]
+    pub oanda_access_token: Option<String>,
+
+    /// Per-request timeout in seconds
+    #[arg(long, global = true, default_value_t = 30)]
+    pub timeout: u64,
+
+    /// Retries for timeouts, HTTP 429 and 5xx responses, with jittered exponential backoff
+    #[arg(long, global = true, default_value_t = 3)]
+    pub retries: u32,
+
+    /// Cap on requests per second across all instruments (OANDA allows 120)
+    #[arg(long, global = true, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_requests_per_second: u32,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Fetch candles newer than the last stored ones (default)
+    Sync(SyncArgs),
+    /// Fetch history older than the earliest stored candle, then sync
+    Backfill(BackfillArgs),
+    /// Keep syncing, fetching each candle shortly after it closes, until stopped
+    Watch(WatchArgs),
+    /// Write stored candles to CSV, NDJSON or Parquet files
+    Export(ExportArgs),
+    /// Check stored series for missing candles, optionally fetching them
+    ///
+    /// Candles are expected around the clock except over the weekend. Once a series
+    /// covers a week, times of day it never has a candle at are taken for the
+    /// instrument's daily break. Holidays are reported as gaps until --repair finds
+    /// OANDA has no candles for them and records them as closures.
+    Verify(VerifyArgs),
+    /// Build bars OANDA doesn't offer, such as 2D, Q or Y, from stored series
+    Resample(ResampleArgs),
+    /// Report the size, extent, staleness, duplicates and gaps of each stored and resampled series
+    Status(StatusArgs),
+    /// List the instruments tradeable on the account
+    ListInstruments(ListInstrumentsArgs),
+    /// Move every per-instrument table into a single `candles` table, which later commands then use
+    Normalize,
+}
+
+/// Which series to sync and how to store them.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SyncArgs {
+    /// Granularity (S5 through H12, D, W, M), overrides the config file [default: D W M]
+    #[arg(short, long, value_enum, num_args = 0.., default_missing_values = ["D", "W", "M"], ignore_case = true)]
+    pub granularity: Vec<Granularity>,
+
+    /// Price components to store (M = mid, B = bid, A = ask), bid and ask go to `_bid`/`_ask` tables, overrides the config file [default: M]
+    #[arg(short, long, value_enum, num_args = 1.., ignore_case = true)]
+    pub price: Vec<PriceComponent>,
+
+    /// Retention as [GRANULARITY=]RULE where RULE is `all`, a bar count (e.g. 2000) or a time window (e.g. 90d, 12w, 5y), repeatable, overrides the config file [default: all]
+    #[arg(short, long, value_parser = parse_retention_rule)]
+    pub retention: Vec<RetentionRule>,
+
+    /// Comma-separated list of tickers (whitelist) to sync instead of the config file's, e.g., --tickers natgas_usd,xau_usd,eur_usd,spx500_usd
+    #[arg(long)]
+    pub tickers: Option<String>,
+
+    /// Only sync this group of the config file, repeatable
+    #[arg(long, conflicts_with = "tickers")]
+    pub group: Vec<String>,
+
+    /// How --tickers entries match instrument names: exact names, glob patterns (xau_*) or regular expressions (^xau_)
+    #[arg(long = "match", value_enum, default_value_t = MatchMode::Exact, ignore_case = true)]
+    pub match_mode: MatchMode,
+
+    /// Also store each series' forming candle in the `live_candles` table, overrides the config file
+    #[arg(long)]
+    pub live: bool,
+
+    /// Hour at which days start for daily and longer candles and for H2 through H12, as [GRANULARITY=]HOUR, repeatable, overrides the config file [default: 17]
+    #[arg(long, value_parser = parse_daily_alignment_rule)]
+    pub daily_alignment: Vec<GranularityRule<u32>>,
+
+    /// Timezone of --daily-alignment as [GRANULARITY=]ZONE, e.g. UTC or Europe/London, repeatable, overrides the config file [default: America/New_York]
+    #[arg(long, value_parser = parse_timezone_rule)]
+    pub alignment_timezone: Vec<GranularityRule<Tz>>,
+
+    /// Day on which weekly candles start, e.g. Monday, overrides the config file [default: Friday]
+    #[arg(long, value_parser = parse_weekday)]
+    pub weekly_alignment: Option<Weekday>,
+
+    /// Number of series fetched concurrently
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    pub concurrency: u32,
+
+    /// Print where each table would be fetched from and roughly how many candles, without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BackfillArgs {
+    #[command(flatten)]
+    pub sync: SyncArgs,
+
+    /// Oldest date to fetch (YYYY-MM-DD or RFC 3339), defaults to the instrument's inception
+    #[arg(long, value_parser = parse_date)]
+    pub from: Option<DateTime<Utc>>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub sync: SyncArgs,
+
+    /// Seconds to wait after a candle closes before fetching it, giving OANDA time to publish it
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(i64).range(0..=86_400))]
+    pub delay: i64,
+
+    /// Seconds between heartbeat lines while waiting for the next close
+    #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(i64).range(1..=86_400))]
+    pub heartbeat: i64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv, ignore_case = true)]
+    pub format: ExportFormat,
+
+    /// Directory for the per-table files, or the file to write with --long [default: export, or candles.<FORMAT> with --long]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Write every table into a single file with instrument, granularity and price columns
+    #[arg(long)]
+    pub long: bool,
+
+    /// Only candles opened at or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    pub from: Option<DateTime<Utc>>,
+
+    /// Only candles opened before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    pub to: Option<DateTime<Utc>>,
+
+    #[command(flatten)]
+    pub series: StoredSeriesArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub series: StoredSeriesArgs,
+
+    /// Fetch the missing candles, recording ranges OANDA has none for as closures
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Output format of the gap report
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table, ignore_case = true)]
+    pub format: OutputFormat,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ResampleArgs {
+    /// Timeframes to build: hours from the Sunday open (4H), days from the start of the week (2D), months from January (2M), Q or Y, each from the longest stored granularity that fits it
+    #[arg(long, required = true, num_args = 1..)]
+    pub to: Vec<Timeframe>,
+
+    /// Hour at which days start for day, month, quarter and year bars [default: 17]
+    #[arg(long, value_parser = parse_hour)]
+    pub daily_alignment: Option<u32>,
+
+    /// Timezone of --daily-alignment, e.g. UTC or Europe/London [default: America/New_York]
+    #[arg(long, value_parser = parse_timezone)]
+    pub alignment_timezone: Option<Tz>,
+
+    /// Rebuild every bar instead of continuing with the last stored one, e.g. after repairing gaps
+    #[arg(long)]
+    pub rebuild: bool,
+
+    #[command(flatten)]
+    pub series: StoredSeriesArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub series: StoredSeriesArgs,
+
+    /// Output format of the report
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table, ignore_case = true)]
+    pub format: OutputFormat,
+}
+
+/// Narrows a command to some of the stored series.
+#[derive(clap::Args, Debug)]
+pub struct StoredSeriesArgs {
+    /// Comma-separated list of tickers [default: all stored]
+    #[arg(long)]
+    pub tickers: Option<String>,
+
+    /// How --tickers entries match instrument names: exact names, glob patterns (xau_*) or regular expressions (^xau_)
+    #[arg(long = "match", value_enum, default_value_t = MatchMode::Exact, ignore_case = true)]
+    pub match_mode: MatchMode,
+
+    /// Only these granularities [default: all stored]
+    #[arg(short, long, value_enum, num_args = 1.., ignore_case = true)]
+    pub granularity: Vec<Granularity>,
+
+    /// Only these price components (M = mid, B = bid, A = ask) [default: all stored]
+    #[arg(short, long, value_enum, num_args = 1.., ignore_case = true)]
+    pub price: Vec<PriceComponent>,
+}
+
+impl StoredSeriesArgs {
+    /// The stored series that pass the filters, failing if there are none.
+    pub fn select(&self, storage: &Storage) -> Result<Vec<SeriesKey>> {
+        let selected = self.matching(storage)?;
+        if selected.is_empty() {
+            return Err(Error::Config("no stored series match the filters".to_string()));
+        }
+        Ok(selected)
+    }
+
+    /// The stored series that pass the filters, possibly none.
+    pub fn matching(&self, storage: &Storage) -> Result<Vec<SeriesKey>> {
+        let whitelist = self.whitelist()?;
+        Ok(storage.series()?
+            .into_iter()
+            .filter(|s| whitelist.as_ref().is_none_or(|w| w.matches(&s.instrument)))
+            .filter(|s| self.granularity.is_empty() || self.granularity.contains(&s.granularity))
+            .filter(|s| self.price.is_empty() || self.price.contains(&s.component))
+            .collect())
+    }
+
+    /// Whether any filter narrows the stored series.
+    pub fn filtered(&self) -> bool {
+        self.tickers.is_some() || !self.granularity.is_empty() || !self.price.is_empty()
+    }
+
+    /// The series built by `resample` that pass the filters, none if --granularity is given.
+    pub fn select_resampled(&self, storage: &Storage) -> Result<Vec<ResampledKey>> {
+        if !self.granularity.is_empty() {
+            return Ok(Vec::new());
+        }
+        let whitelist = self.whitelist()?;
+        Ok(storage.resampled_series()?
+            .into_iter()
+            .filter(|s| whitelist.as_ref().is_none_or(|w| w.matches(&s.instrument)))
+            .filter(|s| self.price.is_empty() || self.price.contains(&s.component))
+            .collect())
+    }
+
+    fn whitelist(&self) -> Result<Option<Whitelist>> {
+        self.tickers.as_ref()
+            .map(|tickers| {
+                let tickers: Vec<&str> = tickers.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+                Whitelist::new(&tickers, self.match_mode)
+            })
+            .transpose()
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ListInstrumentsArgs {
+    /// Only instruments of these types (CURRENCY, CFD, METAL), repeatable
+    #[arg(long = "type")]
+    pub instrument_type: Vec<String>,
+
+    /// Only instruments quoted in these currencies, e.g. USD, repeatable
+    #[arg(long)]
+    pub quote: Vec<String>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table, ignore_case = true)]
+    pub format: OutputFormat,
+}
+
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| format!("expected YYYY-MM-DD or RFC 3339 date: {}", e))
+}
+
+/// A setting that applies to one granularity, or to all of them when `granularity` is `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct GranularityRule<T> {
+    granularity: Option<Granularity>,
+    value: T,
+}
+
+pub type RetentionRule = GranularityRule<Retention>;
+
+/// Parses `[GRANULARITY=]VALUE`, leaving VALUE to `parse`.
+fn parse_rule<T>(s: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<GranularityRule<T>, String> {
+    match s.split_once('=') {
+        Some((granularity, value)) => Ok(GranularityRule {
+            granularity: Some(Granularity::from_str(granularity.trim(), true)?),
+            value: parse(value.trim())?,
+        }),
+        None => Ok(GranularityRule { granularity: None, value: parse(s.trim())? }),
+    }
+}
+
+fn parse_retention_rule(s: &str) -> Result<RetentionRule, String> {
+    parse_rule(s, str::parse)
+}
+
+fn parse_daily_alignment_rule(s: &str) -> Result<GranularityRule<u32>, String> {
+    parse_rule(s, parse_hour)
+}
+
+fn parse_timezone_rule(s: &str) -> Result<GranularityRule<Tz>, String> {
+    parse_rule(s, parse_timezone)
+}
+
+fn parse_hour(s: &str) -> Result<u32, String> {
+    s.trim().parse().ok().filter(|&h| h < 24).ok_or_else(|| format!("expected an hour from 0 to 23, got `{}`", s))
+}
+
+fn parse_timezone(s: &str) -> Result<Tz, String> {
+    s.trim().parse().map_err(|_| format!("unknown timezone `{}`, expected e.g. UTC or Europe/London", s))
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday, String> {
+    s.trim().parse().map_err(|_| format!("expected a weekday such as Monday, got `{}`", s))
+}
+
+/// Later rules win, and a granularity-specific rule beats a general one.
+pub fn rule_for<T: Copy>(rules: &[GranularityRule<T>], granularity: Granularity) -> Option<T> {
+    rules.iter().rev().find(|r| r.granularity == Some(granularity))
+        .or_else(|| rules.iter().rev().find(|r| r.granularity.is_none()))
+        .map(|r| r.value)
+}
+
+/// Resolves a setting from its CLI flag, falling back to an env variable.
+fn flag_or_env(flag: Option<String>, var: &str) -> Result<String> {
+    flag.or_else(|| env::var(var).ok())
+        .ok_or_else(|| Error::Config(format!("{} not set", var)))
+}
+
+/// Builds the API client from flags and env variables, returning it with the account ID.
+pub fn connect(args: &GlobalArgs) -> Result<(OandaClient, String)> {
+    let token = flag_or_env(args.oanda_access_token.clone(), "OANDA_ACCESS_TOKEN")?;
+    let account_id = flag_or_env(args.oanda_account_id.clone(), "OANDA_ACCOUNT_ID")?;
+
+    // Explicit base URL beats environment, and CLI flags beat env variables.
+    let base_url = match args.base_url.clone().or_else(|| env::var("OANDA_BASE_URL").ok()) {
+        Some(url) => url,
+        None => {
+            let environment = match (args.environment, env::var("OANDA_ENVIRONMENT")) {
+                (Some(environment), _) => environment,
+                (None, Ok(name)) => Environment::from_str(&name, true)
+                    .map_err(|_| Error::Config(format!("OANDA_ENVIRONMENT must be live or practice, got `{}`", name)))?,
+                (None, Err(_)) => Environment::Live,
+            };
+            environment.base_url().to_string()
+        }
+    };
+
+    let client = OandaClient::builder(base_url, token)
+        .timeout(Duration::from_secs(args.timeout))
+        .retries(args.retries)
+        .on_retry(|retry| {
+            eprintln!("Request failed, retrying in {:.1}s ({}/{}): {}", retry.delay.as_secs_f64(), retry.attempt, retry.retries, retry.error)
+        })
+        .max_requests_per_second(args.max_requests_per_second)
+        .build()?;
+
+    Ok((client, account_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sync_options_before_a_subcommand_are_refused() {
+        let err = Cli::try_parse_args_from(["oandaohlc", "--dry-run", "sync"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(Cli::try_parse_args_from(["oandaohlc", "-g", "H1", "--tickers", "eur_usd", "backfill"]).is_err());
+    }
+
+    #[test]
+    fn sync_options_go_to_the_command_they_follow() {
+        let cli = Cli::try_parse_args_from(["oandaohlc", "--db", "test.db", "sync", "--dry-run"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Sync(ref args)) if args.dry_run));
+        assert!(!cli.sync.dry_run);
+        assert_eq!(cli.global.db.as_deref(), Some("test.db"));
+
+        let cli = Cli::try_parse_args_from(["oandaohlc", "--dry-run", "-g", "H1"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.sync.dry_run);
+        assert_eq!(cli.sync.granularity, [Granularity::H1]);
+    }
+}
@@ -0,0 +1,12 @@
+use super::config::Config;
+use super::GlobalArgs;
+use oandaohlc::Result;
+
+/// Switches the database to the single `candles` table.
+pub fn normalize(global: &GlobalArgs) -> Result<()> {
+    let config = Config::load(global.config.as_deref())?;
+    let mut storage = config.open_storage(global)?;
+    let moved = storage.normalize()?;
+    println!("Moved {} tables into candles", moved);
+    Ok(())
+}
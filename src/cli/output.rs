@@ -0,0 +1,51 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use oandaohlc::{Error, Result};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// `time` in RFC 3339 to the second, as reports and progress lines show it.
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Prints `rows` as an aligned table or CSV, or `items` as a JSON array.
+pub fn print_output<T: Serialize>(format: OutputFormat, headers: &[&str], rows: &[Vec<String>], items: &[T]) -> Result<()> {
+    match format {
+        OutputFormat::Table => {
+            let widths: Vec<usize> = (0..headers.len())
+                .map(|col| rows.iter().map(|r| r[col].len()).chain([headers[col].len()]).max().unwrap_or(0))
+                .collect();
+            let header: Vec<String> = headers.iter().map(|h| h.to_uppercase()).collect();
+            for row in std::iter::once(&header).chain(rows) {
+                let padded: Vec<String> = row.iter().zip(&widths).map(|(c, w)| format!("{:<w$}", c, w = w)).collect();
+                println!("{}", padded.join("  ").trim_end());
+            }
+        }
+        OutputFormat::Csv => {
+            println!("{}", headers.join(","));
+            for row in rows {
+                println!("{}", row.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(","));
+            }
+        }
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(items).map_err(|e| Error::Config(e.to_string()))?;
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,59 @@
+use super::config::Config;
+use super::{GlobalArgs, ResampleArgs};
+use oandaohlc::{Alignment, ResampledKey, Result, SeriesKey};
+
+/// Builds the --to timeframes for every selected instrument and price component, each
+/// from the longest stored granularity whose candles fit its bars. Returns how many
+/// could not be built.
+pub fn resample(global: &GlobalArgs, args: &ResampleArgs) -> Result<usize> {
+    let config = Config::load(global.config.as_deref())?;
+    let mut storage = config.open_storage(global)?;
+    let selected = args.series.select(&storage)?;
+
+    let default = Alignment::default();
+    let alignment = Alignment::new(
+        args.daily_alignment.unwrap_or(default.daily_hour()),
+        args.alignment_timezone.unwrap_or(default.timezone()),
+        default.weekly(),
+    )?;
+
+    let mut sources = Vec::new();
+    for series in &selected {
+        sources.push((series, storage.alignment(series)?.unwrap_or_default()));
+    }
+    let mut groups: Vec<(&str, _)> = selected.iter().map(|s| (s.instrument.as_str(), s.component)).collect();
+    groups.sort();
+    groups.dedup();
+
+    let mut built = 0;
+    let mut failed = 0;
+    for (instrument, component) in groups {
+        for &timeframe in &args.to {
+            let target = ResampledKey::new(instrument, timeframe, component);
+            let source: Option<&SeriesKey> = sources.iter()
+                .filter(|(s, _)| s.instrument == instrument && s.component == component)
+                .filter(|(s, source_alignment)| timeframe.fits(s.granularity, *source_alignment, alignment))
+                .map(|(s, _)| *s)
+                .max_by_key(|s| s.granularity);
+            let Some(source) = source else {
+                eprintln!("No stored series of {} fits {}", instrument, target.name());
+                failed += 1;
+                continue;
+            };
+
+            match oandaohlc::resample(&mut storage, source, timeframe, alignment, args.rebuild) {
+                Ok(bars) => {
+                    println!("Resampled {} from {}: {} bars", target.name(), source.name(), bars);
+                    built += 1;
+                }
+                Err(e) => {
+                    eprintln!("Failed to resample {}: {}", target.name(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("Resampled {} series", built);
+
+    Ok(failed)
+}
@@ -0,0 +1,120 @@
+use super::config::Config;
+use super::output::{format_time, print_output};
+use super::{GlobalArgs, StatusArgs};
+use chrono::{DateTime, TimeDelta, Utc};
+use oandaohlc::{find_gaps, Error, Result};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct StatusRow<'a> {
+    series: String,
+    instrument: &'a str,
+    granularity: &'a str,
+    price: &'a str,
+    rows: usize,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    /// Close of the latest candle that should be stored by now, none for resampled series.
+    expected_close: Option<DateTime<Utc>>,
+    /// How long before `expected_close` the last stored candle closes.
+    stale_seconds: Option<i64>,
+    /// Candles closed since the last stored one.
+    behind: Option<usize>,
+    duplicates: usize,
+    gaps: Option<usize>,
+    missing: Option<usize>,
+}
+
+/// Lists the selected series with their size, extent, staleness, duplicate timestamps
+/// and gaps, followed by the series built by `resample` with their size and extent,
+/// without changing the database. An empty database makes an empty report, unless
+/// filters were given.
+pub fn status(global: &GlobalArgs, args: &StatusArgs) -> Result<()> {
+    let config = Config::load(global.config.as_deref())?;
+    let db = config.db(global);
+    if !Path::new(&db).exists() {
+        return Err(Error::Config(format!("database `{}` doesn't exist", db)));
+    }
+    let storage = config.open_storage_read_only(global)?;
+    let selected = if args.series.filtered() { args.series.select(&storage)? } else { args.series.matching(&storage)? };
+    let now = Utc::now();
+
+    let mut items = Vec::new();
+    for series in &selected {
+        let alignment = storage.alignment(series)?.unwrap_or_default();
+        let stats = storage.stats(series)?;
+        let gaps = find_gaps(&storage, series, alignment)?;
+        let expected_close = alignment.last_close(series.granularity, now);
+        items.push(StatusRow {
+            series: series.name(),
+            instrument: &series.instrument,
+            granularity: series.granularity.as_str(),
+            price: series.component.name(),
+            rows: stats.rows,
+            first: stats.first,
+            last: stats.last,
+            expected_close: Some(expected_close),
+            stale_seconds: stats.last.map(|last| {
+                (expected_close - alignment.candle(series.granularity, last).1).max(TimeDelta::zero()).num_seconds()
+            }),
+            behind: stats.last.map(|last| alignment.count_candles(series.granularity, last, now)),
+            duplicates: stats.duplicates,
+            gaps: Some(gaps.len()),
+            missing: Some(gaps.iter().map(|gap| gap.missing).sum()),
+        });
+    }
+
+    let resampled = args.series.select_resampled(&storage)?;
+    let timeframes: Vec<String> = resampled.iter().map(|series| series.timeframe.name()).collect();
+    for (series, timeframe) in resampled.iter().zip(&timeframes) {
+        let stats = storage.resampled_stats(series)?;
+        items.push(StatusRow {
+            series: series.name(),
+            instrument: &series.instrument,
+            granularity: timeframe,
+            price: series.component.name(),
+            rows: stats.rows,
+            first: stats.first,
+            last: stats.last,
+            expected_close: None,
+            stale_seconds: None,
+            behind: None,
+            duplicates: stats.duplicates,
+            gaps: None,
+            missing: None,
+        });
+    }
+
+    let rows: Vec<Vec<String>> = items.iter()
+        .map(|row| vec![
+            row.series.clone(),
+            row.rows.to_string(),
+            row.first.map_or_else(|| "-".to_string(), format_time),
+            row.last.map_or_else(|| "-".to_string(), format_time),
+            row.stale_seconds.map_or_else(|| "-".to_string(), |seconds| format_age(TimeDelta::seconds(seconds))),
+            row.behind.map_or_else(|| "-".to_string(), |behind| behind.to_string()),
+            row.duplicates.to_string(),
+            row.gaps.map_or_else(|| "-".to_string(), |gaps| gaps.to_string()),
+            row.missing.map_or_else(|| "-".to_string(), |missing| missing.to_string()),
+        ])
+        .collect();
+    print_output(
+        args.format,
+        &["series", "rows", "first", "last", "stale", "behind", "duplicates", "gaps", "missing"],
+        &rows,
+        &items,
+    )
+}
+
+/// `2d 4h`, `3h 20m` or `45m`, to the minute.
+fn format_age(age: TimeDelta) -> String {
+    let (days, hours, minutes) = (age.num_days(), age.num_hours() % 24, age.num_minutes() % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
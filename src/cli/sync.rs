@@ -0,0 +1,130 @@
+use super::config::Config;
+use super::output::{format_time, print_output, OutputFormat};
+use super::{connect, GlobalArgs, SyncArgs};
+use chrono::{DateTime, Utc};
+use oandaohlc::{OandaClient, Result, SeriesSpec, Storage, SyncEvent};
+
+/// Whether and how far back to fetch history older than what is stored.
+#[derive(Debug, Clone, Copy)]
+pub enum Mode {
+    Forward,
+    Backfill { from: Option<DateTime<Utc>> },
+}
+
+/// How a sync ended, counting the series that failed or, in a dry run, would fail.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Synced { failed: usize },
+    Planned { failing: usize },
+}
+
+/// Syncs every selected series, or with --dry-run only prints the plan.
+pub async fn run_sync(global: &GlobalArgs, args: &SyncArgs, mode: Mode) -> Result<Outcome> {
+    let (client, account_id) = connect(global)?;
+
+    let config = Config::load(global.config.as_deref())?;
+    if args.dry_run {
+        let mut storage = config.open_storage_read_only(global)?;
+        let series = select_series(&client, &account_id, &config, &mut storage, args, mode).await?;
+        return print_plan(&storage, &series, mode).map(|failing| Outcome::Planned { failing });
+    }
+    let mut storage = config.open_storage(global)?;
+    let series = select_series(&client, &account_id, &config, &mut storage, args, mode).await?;
+
+    let failed = oandaohlc::sync(&client, &mut storage, &series, args.concurrency as usize, print_event).await;
+    Ok(Outcome::Synced { failed })
+}
+
+/// Prints what a sync of `series` would fetch, per table. Returns how many series would
+/// fail before fetching.
+fn print_plan(storage: &Storage, series: &[SeriesSpec], mode: Mode) -> Result<usize> {
+    let now = Utc::now();
+    let mut failed = 0;
+    let mut rows = Vec::new();
+    for spec in series {
+        let planned = match oandaohlc::plan(storage, spec, now) {
+            Ok(planned) => planned,
+            Err(error) => {
+                print_event(SyncEvent::Failed { instrument: &spec.instrument, granularity: spec.granularity, error: &error });
+                failed += 1;
+                continue;
+            }
+        };
+        for p in planned {
+            let mut row = vec![
+                p.series.name(),
+                p.from.map_or_else(|| "newest page".to_string(), format_time),
+                p.candles.to_string(),
+            ];
+            if let Some(backfill) = p.backfill {
+                row.extend([
+                    backfill.from.map_or_else(|| "inception".to_string(), format_time),
+                    backfill.to.map_or_else(|| "newest".to_string(), format_time),
+                    backfill.candles.map_or_else(|| "?".to_string(), |c| c.to_string()),
+                ]);
+            }
+            rows.push(row);
+        }
+    }
+
+    let headers: &[&str] = match mode {
+        Mode::Forward => &["table", "from", "candles"],
+        Mode::Backfill { .. } => &["table", "from", "candles", "backfill from", "backfill to", "backfill candles"],
+    };
+    if !rows.is_empty() {
+        print_output::<()>(OutputFormat::Table, headers, &rows, &[])?;
+    }
+    println!("Dry run: nothing was fetched or written");
+    Ok(failed)
+}
+
+/// Fetches the account's instruments, records their metadata and resolves the series
+/// to sync from the config file and flags.
+pub async fn select_series(
+    client: &OandaClient,
+    account_id: &str,
+    config: &Config,
+    storage: &mut Storage,
+    args: &SyncArgs,
+    mode: Mode,
+) -> Result<Vec<SeriesSpec>> {
+    let all_instruments = client.instruments(account_id).await?;
+    let selected = config.select(&all_instruments, args)?;
+
+    let (backfill, backfill_from) = match mode {
+        Mode::Forward => (false, None),
+        Mode::Backfill { from } => (true, from),
+    };
+    let series = selected.iter()
+        .flat_map(|(inst, settings)| settings.granularities.iter().map(move |&granularity| Ok(SeriesSpec {
+            instrument: inst.name.clone(),
+            granularity,
+            components: settings.components.clone(),
+            retention: settings.retention(granularity),
+            backfill,
+            backfill_from,
+            live: settings.live,
+            alignment: settings.alignment(granularity)?,
+        })))
+        .collect::<Result<_>>()?;
+
+    if !args.dry_run {
+        let changed = storage.store_instruments(&all_instruments)?;
+        if changed > 0 {
+            println!("Recorded metadata changes for {} instruments", changed);
+        }
+    }
+
+    Ok(series)
+}
+
+pub fn print_event(event: SyncEvent) {
+    match event {
+        SyncEvent::Deduplicated { series, removed } => println!("Removed {} duplicate candles from {}", removed, series.name()),
+        SyncEvent::Stored { series, candles, backfill: true } => println!("Backfilled {} candles for {}", candles, series.name()),
+        SyncEvent::Stored { series, candles, backfill: false } => println!("Fetched {} candles for {}", candles, series.name()),
+        SyncEvent::Failed { instrument, granularity, error } => {
+            eprintln!("Failed to sync {} {}: {}", instrument, granularity.as_str(), error)
+        }
+    }
+}
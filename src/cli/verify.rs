@@ -0,0 +1,79 @@
+use super::config::Config;
+use super::output::{format_time, print_output};
+use super::{connect, GlobalArgs, VerifyArgs};
+use oandaohlc::{find_gaps, repair, Gap, Repaired, Result};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct GapRow<'a> {
+    series: String,
+    instrument: &'a str,
+    granularity: &'a str,
+    price: &'a str,
+    #[serde(flatten)]
+    gap: Gap,
+}
+
+/// Reports the candles missing from the selected series and, with --repair, fetches them.
+/// Returns how many gaps could not be repaired.
+pub async fn verify(global: &GlobalArgs, args: &VerifyArgs) -> Result<usize> {
+    let config = Config::load(global.config.as_deref())?;
+    let mut storage = if args.repair { config.open_storage(global)? } else { config.open_storage_read_only(global)? };
+    let selected = args.series.select(&storage)?;
+
+    let mut found = Vec::new();
+    for series in &selected {
+        let alignment = storage.alignment(series)?.unwrap_or_default();
+        found.extend(find_gaps(&storage, series, alignment)?.into_iter().map(|gap| (series, alignment, gap)));
+    }
+    if found.is_empty() {
+        println!("No gaps in {} series", selected.len());
+        return Ok(0);
+    }
+
+    let items: Vec<GapRow> = found.iter()
+        .map(|(series, _, gap)| GapRow {
+            series: series.name(),
+            instrument: &series.instrument,
+            granularity: series.granularity.as_str(),
+            price: series.component.name(),
+            gap: *gap,
+        })
+        .collect();
+    let rows: Vec<Vec<String>> = items.iter()
+        .map(|row| vec![
+            row.series.clone(),
+            format_time(row.gap.from),
+            format_time(row.gap.to),
+            row.gap.missing.to_string(),
+        ])
+        .collect();
+    print_output(args.format, &["series", "from", "to", "missing"], &rows, &items)?;
+
+    if !args.repair {
+        return Ok(0);
+    }
+
+    let (client, _) = connect(global)?;
+    let mut total = Repaired::default();
+    let mut failed = 0;
+    for (series, alignment, gap) in &found {
+        match repair(&client, &mut storage, series, *alignment, *gap).await {
+            Ok(repaired) => {
+                println!(
+                    "Repaired {} from {}: fetched {} candles, {} closures",
+                    series.name(), format_time(gap.from), repaired.fetched, repaired.closures
+                );
+                total.fetched += repaired.fetched;
+                total.closures += repaired.closures;
+            }
+            Err(e) => {
+                eprintln!("Failed to repair {} from {}: {}", series.name(), format_time(gap.from), e);
+                failed += 1;
+            }
+        }
+    }
+    println!("Fetched {} candles and recorded {} closures for {} gaps", total.fetched, total.closures, found.len() - failed);
+
+    Ok(failed)
+}
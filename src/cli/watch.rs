@@ -0,0 +1,122 @@
+use super::config::Config;
+use super::output::format_time;
+use super::sync::{print_event, select_series, Mode};
+use super::{connect, GlobalArgs, WatchArgs};
+use chrono::{DateTime, TimeDelta, Utc};
+use oandaohlc::{Error, Granularity, Result, SeriesSpec};
+use std::collections::BTreeSet;
+
+/// How often the account's instruments are fetched again, picking up new ones and
+/// metadata changes.
+const REFRESH_INSTRUMENTS: TimeDelta = TimeDelta::hours(1);
+
+/// Syncs every selected series once, then again shortly after each of its candles
+/// closes, until SIGTERM or Ctrl-C. A sync that is running when the signal arrives
+/// is finished first.
+pub async fn watch(global: &GlobalArgs, args: &WatchArgs) -> Result<()> {
+    if args.sync.dry_run {
+        return Err(Error::Config("watch has no dry run, try sync --dry-run".to_string()));
+    }
+    let mut shutdown = Shutdown::listen()?;
+    let (client, account_id) = connect(global)?;
+
+    let config = Config::load(global.config.as_deref())?;
+    let mut storage = config.open_storage(global)?;
+    let mut series = select_series(&client, &account_id, &config, &mut storage, &args.sync, Mode::Forward).await?;
+    let mut selected_at = Utc::now();
+    if series.is_empty() {
+        return Err(Error::Config("no series to watch".to_string()));
+    }
+
+    let delay = TimeDelta::seconds(args.delay);
+    let heartbeat = TimeDelta::seconds(args.heartbeat);
+
+    println!("Catching up {} series", series.len());
+    let mut due: Vec<SeriesSpec> = series.clone();
+
+    loop {
+        let failed = oandaohlc::sync(&client, &mut storage, &due, args.sync.concurrency as usize, print_event).await;
+        if failed > 0 {
+            eprintln!("Sync finished with {} failed series", failed);
+        }
+
+        let now = Utc::now();
+        let closes: Vec<DateTime<Utc>> = series.iter().map(|s| s.alignment.next_close(s.granularity, now)).collect();
+        let close = closes.iter().copied().min().expect("at least one series");
+        let next: BTreeSet<Granularity> = series.iter().zip(&closes)
+            .filter(|&(_, &c)| c == close)
+            .map(|(s, _)| s.granularity)
+            .collect();
+        let wake = close + delay;
+        let names = next.iter().map(|g| g.as_str()).collect::<Vec<_>>().join(", ");
+        println!("Next sync of {} at {}", names, format_time(wake));
+
+        // The wall clock is checked after every nap, so a suspended machine catches up
+        // as soon as it wakes.
+        loop {
+            let now = Utc::now();
+            if now >= wake {
+                break;
+            }
+            let nap = (wake - now).min(heartbeat).to_std().unwrap_or_default();
+            tokio::select! {
+                () = tokio::time::sleep(nap) => {
+                    if Utc::now() < wake {
+                        println!("Heartbeat: watching {} series, next sync of {} at {}", series.len(), names, format_time(wake));
+                    }
+                }
+                () = shutdown.recv() => {
+                    println!("Stopped watching");
+                    return Ok(());
+                }
+            }
+        }
+
+        if Utc::now() - selected_at >= REFRESH_INSTRUMENTS {
+            match select_series(&client, &account_id, &config, &mut storage, &args.sync, Mode::Forward).await {
+                Ok(selected) if !selected.is_empty() => {
+                    series = selected;
+                    selected_at = Utc::now();
+                }
+                Ok(_) => eprintln!("No series selected any more, keeping the previous selection"),
+                Err(e) => eprintln!("Failed to refresh instruments, keeping the previous selection: {}", e),
+            }
+        }
+        // A refresh may have changed the selection, so the due series are picked anew.
+        due = series.iter()
+            .filter(|s| s.alignment.next_close(s.granularity, close - TimeDelta::seconds(1)) == close)
+            .cloned()
+            .collect();
+    }
+}
+
+/// Signals that stop `watch`. They are registered up front, so one arriving mid-sync
+/// waits for the sync instead of killing the process.
+struct Shutdown {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+    fn listen() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            Ok(Shutdown { terminate: signal(SignalKind::terminate())?, interrupt: signal(SignalKind::interrupt())? })
+        }
+        #[cfg(not(unix))]
+        Ok(Shutdown {})
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.terminate.recv() => {}
+            _ = self.interrupt.recv() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
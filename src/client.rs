@@ -0,0 +1,289 @@
+use crate::candle::{Candle, Granularity, PriceComponent};
+use crate::error::{Error, Result};
+use crate::schedule::Alignment;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, sleep_until, Instant};
+
+const LIVE_URL: &str = "https://api-fxtrade.oanda.com/v3";
+const PRACTICE_URL: &str = "https://api-fxpractice.oanda.com/v3";
+
+/// Most candles OANDA returns for one request.
+pub const MAX_CANDLES_PER_REQUEST: usize = 5000;
+
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// OANDA API environments.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Environment {
+    Live,
+    Practice,
+}
+
+impl Environment {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Environment::Live => LIVE_URL,
+            Environment::Practice => PRACTICE_URL,
+        }
+    }
+}
+
+/// Instrument tradeable on an account. Sizes, distances and the margin rate are
+/// decimal strings exactly as OANDA sends them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instrument {
+    pub name: String,
+    /// `CURRENCY`, `CFD` or `METAL`.
+    #[serde(rename = "type")]
+    pub instrument_type: String,
+    pub display_name: String,
+    /// Position of the pip relative to the decimal point, e.g. -4 for EUR_USD.
+    pub pip_location: i32,
+    /// Number of decimals in displayed prices.
+    pub display_precision: u32,
+    #[serde(default)]
+    pub trade_units_precision: i32,
+    #[serde(default)]
+    pub minimum_trade_size: String,
+    #[serde(default)]
+    pub maximum_trailing_stop_distance: String,
+    #[serde(default)]
+    pub minimum_trailing_stop_distance: String,
+    #[serde(default)]
+    pub maximum_position_size: String,
+    #[serde(default)]
+    pub maximum_order_units: String,
+    #[serde(default)]
+    pub margin_rate: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OandaInstruments {
+    instruments: Vec<Instrument>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CandleResponse {
+    candles: Vec<Candle>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OandaError {
+    #[serde(rename = "errorMessage")]
+    error_message: String,
+}
+
+/// Window of candles to request. OANDA rejects `count` when both `from` and `to` are given.
+#[derive(Debug, Clone, Copy)]
+pub struct CandleQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub count: usize,
+    /// Where days and weeks start, sent only where it differs from OANDA's default.
+    pub alignment: Alignment,
+}
+
+/// A transient failure about to be retried, passed to [`OandaClientBuilder::on_retry`].
+#[derive(Debug)]
+pub struct Retry<'a> {
+    pub error: &'a Error,
+    /// How long the client waits before the next attempt.
+    pub delay: Duration,
+    /// Number of the coming retry, from 1 to `retries`.
+    pub attempt: u32,
+    pub retries: u32,
+}
+
+type RetryCallback = Box<dyn Fn(&Retry) + Send + Sync>;
+
+/// Client for the OANDA v3 REST API.
+///
+/// Every request goes through a shared rate limiter, and timeouts, HTTP 429 and 5xx
+/// responses are retried with jittered exponential backoff, honoring `Retry-After`.
+pub struct OandaClient {
+    http: Client,
+    base_url: String,
+    token: String,
+    retries: u32,
+    on_retry: Option<RetryCallback>,
+    limiter: RateLimiter,
+}
+
+/// Configures an [`OandaClient`]. Created by [`OandaClient::builder`].
+pub struct OandaClientBuilder {
+    base_url: String,
+    token: String,
+    timeout: Duration,
+    retries: u32,
+    on_retry: Option<RetryCallback>,
+    max_requests_per_second: u32,
+}
+
+impl OandaClientBuilder {
+    /// Per-request timeout, 30 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retries of transient failures, 3 by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Called before each retry, e.g. to log it. The client itself prints nothing.
+    pub fn on_retry(mut self, callback: impl Fn(&Retry) + Send + Sync + 'static) -> Self {
+        self.on_retry = Some(Box::new(callback));
+        self
+    }
+
+    /// Cap on requests per second across all callers of the client, 100 by default
+    /// (OANDA allows 120).
+    pub fn max_requests_per_second(mut self, requests: u32) -> Self {
+        self.max_requests_per_second = requests;
+        self
+    }
+
+    pub fn build(self) -> Result<OandaClient> {
+        Ok(OandaClient {
+            http: Client::builder().timeout(self.timeout).build()?,
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            token: self.token,
+            retries: self.retries,
+            on_retry: self.on_retry,
+            limiter: RateLimiter::per_second(self.max_requests_per_second),
+        })
+    }
+}
+
+impl OandaClient {
+    /// Starts configuring a client for `base_url`, e.g. [`Environment::base_url`].
+    pub fn builder(base_url: impl Into<String>, token: impl Into<String>) -> OandaClientBuilder {
+        OandaClientBuilder {
+            base_url: base_url.into(),
+            token: token.into(),
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            on_retry: None,
+            max_requests_per_second: 100,
+        }
+    }
+
+    /// Client with default settings.
+    pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Result<Self> {
+        Self::builder(base_url, token).build()
+    }
+
+    /// Instruments tradeable on the account.
+    pub async fn instruments(&self, account_id: &str) -> Result<Vec<Instrument>> {
+        let url = format!("{}/accounts/{}/instruments", self.base_url, account_id);
+        let res: OandaInstruments = self.send_json(self.http.get(url).bearer_auth(&self.token)).await?;
+
+        Ok(res.instruments)
+    }
+
+    /// Candles of `instrument` with every requested price component filled in.
+    pub async fn candles(&self, instrument: &str, granularity: Granularity, components: &[PriceComponent], query: CandleQuery) -> Result<Vec<Candle>> {
+        let price: String = components.iter().map(|c| c.as_str()).collect();
+        let mut req = self.http
+            .get(format!("{}/instruments/{}/candles", self.base_url, instrument))
+            .bearer_auth(&self.token)
+            .query(&[("price", price.as_str()), ("granularity", granularity.as_str())]);
+
+        if let Some(from_time) = query.from {
+            req = req.query(&[("from", from_time.timestamp().to_string())]);
+        }
+        if let Some(to_time) = query.to {
+            req = req.query(&[("to", to_time.timestamp().to_string())]);
+        }
+        if query.from.is_none() || query.to.is_none() {
+            req = req.query(&[("count", query.count.to_string())]);
+        }
+        req = req.query(&query.alignment.query());
+
+        let res: CandleResponse = self.send_json(req).await?;
+        Ok(res.candles)
+    }
+
+    /// Sends the request and decodes a successful JSON body, turning OANDA's error
+    /// responses into `Error::Api` with the `errorMessage` they carry. Transient
+    /// failures are retried up to `self.retries` times, waiting for `Retry-After`
+    /// when OANDA sends one and for a jittered exponential backoff otherwise.
+    async fn send_json<T: for<'de> Deserialize<'de>>(&self, req: RequestBuilder) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire().await;
+            let (error, retry_after) = match try_send_json(req.try_clone().expect("GET requests have no streaming body")).await {
+                Ok(value) => return Ok(value),
+                Err((e, retry_after)) if attempt < self.retries && is_transient(&e) => (e, retry_after),
+                Err((e, _)) => return Err(e),
+            };
+
+            let backoff = BACKOFF_BASE.saturating_mul(1 << attempt.min(16)).min(BACKOFF_MAX);
+            let delay = retry_after.unwrap_or_else(|| backoff.mul_f64(0.5 + fastrand::f64() / 2.0));
+            attempt += 1;
+            if let Some(on_retry) = &self.on_retry {
+                on_retry(&Retry { error: &error, delay, attempt, retries: self.retries });
+            }
+            sleep(delay).await;
+        }
+    }
+}
+
+/// A single attempt, returning the server's `Retry-After` hint alongside any error.
+async fn try_send_json<T: for<'de> Deserialize<'de>>(req: RequestBuilder) -> Result<T, (Error, Option<Duration>)> {
+    let res = req.send().await.map_err(|e| (e.into(), None))?;
+    let status = res.status();
+    if !status.is_success() {
+        let retry_after = res.headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs);
+        let body = res.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<OandaError>(&body).map_or(body, |e| e.error_message);
+        return Err((Error::Api { status, message }, retry_after));
+    }
+    res.json().await.map_err(|e| (e.into(), None))
+}
+
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Http(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        Error::Api { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Spaces requests evenly so that no more than one goes out per `interval`.
+struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn per_second(requests: u32) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs(1) / requests.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn acquire(&self) {
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
@@ -0,0 +1,75 @@
+use crate::schedule::Alignment;
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Everything that can go wrong while talking to OANDA or the database.
+#[derive(Debug)]
+pub enum Error {
+    /// Missing or invalid settings such as credentials.
+    Config(String),
+    /// The request never produced a response, or the body could not be decoded.
+    Http(reqwest::Error),
+    /// OANDA answered with a non-success status.
+    Api { status: StatusCode, message: String },
+    Db(rusqlite::Error),
+    /// A price that is not a number, or that has more decimals than its column can store.
+    Price(String),
+    /// Whitelist entries that matched no instrument on the account.
+    UnmatchedWhitelist(Vec<String>),
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// Candles with another alignment would be mixed into a series.
+    AlignmentMismatch { series: String, stored: Alignment, requested: Alignment },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "{}", msg),
+            Error::Http(e) => write!(f, "HTTP error: {}", e),
+            Error::Api { status, message } => write!(f, "OANDA returned {}: {}", status, message),
+            Error::Db(e) => write!(f, "database error: {}", e),
+            Error::Price(value) => write!(f, "unparsable price `{}`", value),
+            Error::UnmatchedWhitelist(entries) => {
+                write!(f, "no instrument on the account matches: {}", entries.join(", "))
+            }
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::AlignmentMismatch { series, stored, requested } => write!(
+                f,
+                "{} holds candles aligned to {}, not {}; store them in another database or drop it first",
+                series, stored, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Db(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Db(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
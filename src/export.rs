@@ -0,0 +1,267 @@
+use crate::error::{Error, Result};
+use crate::storage::{SeriesKey, StoredCandle};
+use chrono::SecondsFormat;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// File formats candles can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ExportFormat {
+    Csv,
+    /// JSON Lines, one object per candle.
+    Ndjson,
+    /// Needs the `parquet` feature, which is on by default.
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Writes stored candles to a file, or any other writer. In long format every row also
+/// names its instrument, granularity and price component, so many tables fit in one file.
+pub struct CandleWriter<W: Write + Send = File> {
+    long: bool,
+    sink: Sink<W>,
+}
+
+enum Sink<W: Write + Send> {
+    Csv(BufWriter<W>),
+    Ndjson(BufWriter<W>),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet::arrow::ArrowWriter<W>>),
+}
+
+#[derive(Serialize)]
+struct LongRow<'a> {
+    instrument: &'a str,
+    granularity: &'a str,
+    price: &'a str,
+    #[serde(flatten)]
+    candle: &'a StoredCandle,
+}
+
+impl CandleWriter {
+    /// Creates or truncates `path`.
+    pub fn create(path: impl AsRef<Path>, format: ExportFormat, long: bool) -> Result<Self> {
+        CandleWriter::new(File::create(path)?, format, long)
+    }
+}
+
+impl<W: Write + Send> CandleWriter<W> {
+    /// Writes to `out`, starting with the header for CSV.
+    pub fn new(out: W, format: ExportFormat, long: bool) -> Result<Self> {
+        let sink = match format {
+            ExportFormat::Csv => {
+                let mut out = BufWriter::new(out);
+                if long {
+                    write!(out, "instrument,granularity,price,")?;
+                }
+                writeln!(out, "timestamp,open,high,low,close,volume")?;
+                Sink::Csv(out)
+            }
+            ExportFormat::Ndjson => Sink::Ndjson(BufWriter::new(out)),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => Sink::Parquet(Box::new(
+                parquet::arrow::ArrowWriter::try_new(out, parquet_schema(long), None).map_err(std::io::Error::other)?,
+            )),
+            #[cfg(not(feature = "parquet"))]
+            ExportFormat::Parquet => {
+                return Err(Error::Config("built without Parquet support, rebuild with the `parquet` feature".to_string()));
+            }
+        };
+
+        Ok(CandleWriter { long, sink })
+    }
+
+    /// Appends the candles of `series`.
+    pub fn write(&mut self, series: &SeriesKey, candles: &[StoredCandle]) -> Result<()> {
+        let price = series.component.name();
+        match &mut self.sink {
+            Sink::Csv(out) => {
+                for c in candles {
+                    if self.long {
+                        write!(out, "{},{},{},", series.instrument, series.granularity.as_str(), price)?;
+                    }
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{}",
+                        c.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        c.open, c.high, c.low, c.close, c.volume
+                    )?;
+                }
+            }
+            Sink::Ndjson(out) => {
+                for candle in candles {
+                    let line = if self.long {
+                        let row = LongRow { instrument: &series.instrument, granularity: series.granularity.as_str(), price, candle };
+                        serde_json::to_string(&row)
+                    } else {
+                        serde_json::to_string(candle)
+                    };
+                    writeln!(out, "{}", line.map_err(|e| Error::Io(e.into()))?)?;
+                }
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => {
+                if !candles.is_empty() {
+                    let batch = parquet_batch(self.long, series, candles).map_err(std::io::Error::other)?;
+                    writer.write(&batch).map_err(std::io::Error::other)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes buffered rows and, for Parquet, writes the footer, then hands back the
+    /// writer. Dropping the writer without calling this can leave a truncated file.
+    pub fn finish(self) -> Result<W> {
+        Ok(match self.sink {
+            Sink::Csv(out) | Sink::Ndjson(out) => out.into_inner().map_err(|e| e.into_error())?,
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.into_inner().map_err(std::io::Error::other)?,
+        })
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_schema(long: bool) -> arrow_schema::SchemaRef {
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+
+    let labels = ["instrument", "granularity", "price"].map(|name| Field::new(name, DataType::Utf8, false));
+    let timestamp = Field::new("timestamp", DataType::Timestamp(TimeUnit::Second, Some("UTC".into())), false);
+    let prices = ["open", "high", "low", "close", "volume"].map(|name| Field::new(name, DataType::Float64, false));
+
+    let fields: Vec<Field> = if long { labels.to_vec() } else { Vec::new() }
+        .into_iter()
+        .chain([timestamp])
+        .chain(prices)
+        .collect();
+    std::sync::Arc::new(Schema::new(fields))
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_batch(
+    long: bool,
+    series: &SeriesKey,
+    candles: &[StoredCandle],
+) -> std::result::Result<arrow_array::RecordBatch, arrow_schema::ArrowError> {
+    use arrow_array::{ArrayRef, Float64Array, StringArray, TimestampSecondArray};
+    use rust_decimal::prelude::ToPrimitive;
+    use std::sync::Arc;
+
+    let label = |value: &str| Arc::new(StringArray::from(vec![value; candles.len()])) as ArrayRef;
+    let price = |f: fn(&StoredCandle) -> f64| Arc::new(Float64Array::from_iter_values(candles.iter().map(f))) as ArrayRef;
+
+    let mut columns = Vec::new();
+    if long {
+        columns.extend([label(&series.instrument), label(series.granularity.as_str()), label(series.component.name())]);
+    }
+    columns.push(Arc::new(
+        TimestampSecondArray::from_iter_values(candles.iter().map(|c| c.timestamp.timestamp())).with_timezone("UTC"),
+    ));
+    // Parquet decimals have one scale per column, which long files can't share.
+    columns.extend([
+        price(|c| c.open.to_f64().unwrap_or(f64::NAN)),
+        price(|c| c.high.to_f64().unwrap_or(f64::NAN)),
+        price(|c| c.low.to_f64().unwrap_or(f64::NAN)),
+        price(|c| c.close.to_f64().unwrap_or(f64::NAN)),
+        price(|c| c.volume),
+    ]);
+
+    arrow_array::RecordBatch::try_new(parquet_schema(long), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candle::{Granularity, PriceComponent};
+    use crate::test_util::{price, utc};
+
+    fn candles() -> Vec<StoredCandle> {
+        vec![
+            StoredCandle {
+                timestamp: utc("2026-10-14T21:00:00Z"),
+                open: price("1.08545"),
+                high: price("1.0861"),
+                low: price("1.085"),
+                close: price("1.085905"),
+                volume: 42.0,
+            },
+            StoredCandle {
+                timestamp: utc("2026-10-15T21:00:00Z"),
+                open: price("1.0859"),
+                high: price("1.087"),
+                low: price("1.0855"),
+                close: price("1.0866"),
+                volume: 7.5,
+            },
+        ]
+    }
+
+    fn export(format: ExportFormat, long: bool) -> String {
+        let series = SeriesKey::new("EUR_USD", Granularity::D, PriceComponent::B);
+        let mut writer = CandleWriter::new(Vec::new(), format, long).unwrap();
+        writer.write(&series, &candles()).unwrap();
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_has_a_header_and_unquoted_exact_prices() {
+        assert_eq!(
+            export(ExportFormat::Csv, false),
+            "timestamp,open,high,low,close,volume\n\
+             2026-10-14T21:00:00Z,1.08545,1.0861,1.085,1.085905,42\n\
+             2026-10-15T21:00:00Z,1.0859,1.087,1.0855,1.0866,7.5\n"
+        );
+        let long = export(ExportFormat::Csv, true);
+        let mut lines = long.lines();
+        assert_eq!(lines.next(), Some("instrument,granularity,price,timestamp,open,high,low,close,volume"));
+        assert_eq!(lines.next(), Some("EUR_USD,D,bid,2026-10-14T21:00:00Z,1.08545,1.0861,1.085,1.085905,42"));
+        assert_eq!(lines.count(), 1);
+    }
+
+    #[test]
+    fn ndjson_has_one_object_per_candle() {
+        let rows: Vec<serde_json::Value> = export(ExportFormat::Ndjson, true)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            rows[0],
+            serde_json::json!({
+                "instrument": "EUR_USD", "granularity": "D", "price": "bid",
+                "timestamp": "2026-10-14T21:00:00Z", "open": 1.08545, "high": 1.0861, "low": 1.085, "close": 1.085905, "volume": 42.0,
+            })
+        );
+        assert_eq!(rows.len(), 2);
+
+        let short = export(ExportFormat::Ndjson, false);
+        let row: serde_json::Value = serde_json::from_str(short.lines().next().unwrap()).unwrap();
+        assert!(row.get("instrument").is_none());
+        assert_eq!(row["close"], serde_json::json!(1.085905));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_columns_follow_the_csv_header() {
+        let names = |long| -> Vec<String> { parquet_schema(long).fields().iter().map(|f| f.name().clone()).collect() };
+        assert_eq!(names(false), ["timestamp", "open", "high", "low", "close", "volume"]);
+        assert_eq!(names(true), ["instrument", "granularity", "price", "timestamp", "open", "high", "low", "close", "volume"]);
+
+        let series = SeriesKey::new("EUR_USD", Granularity::D, PriceComponent::B);
+        let mut writer = CandleWriter::new(Vec::new(), ExportFormat::Parquet, true).unwrap();
+        writer.write(&series, &candles()).unwrap();
+        let file = writer.finish().unwrap();
+        assert!(file.starts_with(b"PAR1") && file.ends_with(b"PAR1"));
+    }
+}
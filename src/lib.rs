@@ -0,0 +1,51 @@
+//! Downloads OHLC candles from the OANDA v3 REST API into SQLite.
+//!
+//! [`OandaClient`] talks to OANDA, [`Storage`] owns the database and [`sync()`] ties
+//! the two together the way the `oandaohlc` binary does:
+//!
+//! ```no_run
+//! use oandaohlc::{Alignment, Environment, Granularity, OandaClient, PriceComponent, Retention, SeriesSpec, Storage};
+//!
+//! # async fn run() -> oandaohlc::Result<()> {
+//! let client = OandaClient::new(Environment::Practice.base_url(), "token")?;
+//! let mut storage = Storage::open("oanda.db")?;
+//! let series = [SeriesSpec {
+//!     instrument: "EUR_USD".to_string(),
+//!     granularity: Granularity::H1,
+//!     components: vec![PriceComponent::M],
+//!     retention: Retention::All,
+//!     backfill: false,
+//!     backfill_from: None,
+//!     live: false,
+//!     alignment: Alignment::default(),
+//! }];
+//! let failed = oandaohlc::sync(&client, &mut storage, &series, 4, |event| println!("{:?}", event)).await;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod candle;
+pub mod client;
+pub mod error;
+pub mod export;
+pub mod resample;
+pub mod schedule;
+pub mod storage;
+pub mod sync;
+pub mod verify;
+pub mod whitelist;
+
+#[cfg(test)]
+mod test_util;
+
+pub use candle::{Candle, Granularity, Ohlc, PriceComponent};
+pub use client::{CandleQuery, Environment, Instrument, OandaClient, OandaClientBuilder, Retry};
+pub use error::{Error, Result};
+pub use export::{CandleWriter, ExportFormat};
+pub use resample::{resample, Timeframe};
+pub use rust_decimal::Decimal;
+pub use schedule::Alignment;
+pub use storage::{table_name, Layout, PriceFormat, ResampledKey, Retention, SeriesKey, SeriesStats, Storage, StoredCandle};
+pub use sync::{plan, sync, PlannedBackfill, PlannedSeries, SeriesSpec, SyncEvent};
+pub use verify::{find_gaps, repair, Gap, Repaired};
+pub use whitelist::{MatchMode, Whitelist};
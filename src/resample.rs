@@ -0,0 +1,230 @@
+use crate::candle::Granularity;
+use crate::error::{Error, Result};
+use crate::schedule::Alignment;
+use crate::storage::{ResampledKey, SeriesKey, Storage, StoredCandle};
+use chrono::TimeDelta;
+use std::str::FromStr;
+
+/// A bar length OANDA has no candles for, built by [`resample`] from a stored series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Timeframe {
+    /// Hours counted from the Sunday open, 1 to 120. The week's last bar is cut short
+    /// by the Friday close when they don't divide the week.
+    Hours(u32),
+    /// Days counted from the first of the week, 1 to 6.
+    Days(u32),
+    /// Months counted from January, a divisor of 12.
+    Months(u32),
+}
+
+impl Timeframe {
+    /// `4H`, `2D` or `2M`, with `Q` and `Y` for three and twelve months.
+    pub fn name(&self) -> String {
+        match self {
+            Timeframe::Hours(hours) => format!("{}H", hours),
+            Timeframe::Days(days) => format!("{}D", days),
+            Timeframe::Months(3) => "Q".to_string(),
+            Timeframe::Months(12) => "Y".to_string(),
+            Timeframe::Months(months) => format!("{}M", months),
+        }
+    }
+
+    /// The part of `alignment` that affects bars of `self`: hour bars follow the Sunday
+    /// open whatever it is, and no bar depends on the weekly alignment.
+    pub fn alignment(&self, alignment: Alignment) -> Alignment {
+        match self {
+            Timeframe::Hours(_) => Alignment::default(),
+            Timeframe::Days(_) | Timeframe::Months(_) => alignment.for_granularity(Granularity::D),
+        }
+    }
+
+    /// Whether each candle of `granularity`, aligned to `source`, falls within a single
+    /// bar of `self` aligned to `alignment`.
+    pub fn fits(&self, granularity: Granularity, source: Alignment, alignment: Alignment) -> bool {
+        let alignment = self.alignment(alignment);
+        if source.for_granularity(granularity) != alignment.for_granularity(granularity) {
+            return false;
+        }
+        match (self, granularity.duration()) {
+            (Timeframe::Months(_), None) => true,
+            (_, None) => false,
+            (Timeframe::Hours(hours), Some(length)) => {
+                length < TimeDelta::days(1) && TimeDelta::hours(i64::from(*hours)).num_seconds() % length.num_seconds() == 0
+            }
+            (Timeframe::Days(_) | Timeframe::Months(_), Some(length)) => length <= TimeDelta::days(1),
+        }
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = String;
+
+    /// Parses a [`Timeframe::name`], ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_uppercase();
+        let timeframe = match s.as_str() {
+            "Q" => Some(Timeframe::Months(3)),
+            "Y" => Some(Timeframe::Months(12)),
+            _ => s.char_indices().last().and_then(|(at, unit)| {
+                let count: u32 = s[..at].parse().ok()?;
+                match unit {
+                    'H' if (1..=120).contains(&count) => Some(Timeframe::Hours(count)),
+                    'D' if (1..=6).contains(&count) => Some(Timeframe::Days(count)),
+                    'M' if count > 0 && 12 % count == 0 => Some(Timeframe::Months(count)),
+                    _ => None,
+                }
+            }),
+        };
+        timeframe.ok_or_else(|| format!("invalid timeframe `{}`, expected e.g. 4H, 2D, 2M, Q or Y", s))
+    }
+}
+
+/// Builds bars of `timeframe` from the candles of `source` and stores them, continuing
+/// with the last stored bar, or from the first candle if `rebuild` is set. Bars take
+/// the first open, highest high, lowest low and last close of their candles and the
+/// sum of their volumes. A bar is only written once the candle that closes it is
+/// stored, and the first one is skipped if `source` starts partway into it. Returns
+/// the number of bars written.
+pub fn resample(storage: &mut Storage, source: &SeriesKey, timeframe: Timeframe, alignment: Alignment, rebuild: bool) -> Result<usize> {
+    let series = ResampledKey::new(&source.instrument, timeframe, source.component);
+    let alignment = timeframe.alignment(alignment);
+    let source_alignment = storage.alignment(source)?.unwrap_or_default();
+    if !timeframe.fits(source.granularity, source_alignment, alignment) {
+        return Err(Error::Config(format!(
+            "{} cannot be built from {}, as its candles don't fall within single bars",
+            series.name(),
+            source.name()
+        )));
+    }
+    storage.check_resampled_alignment(&series, alignment)?;
+
+    let from = if rebuild { None } else { storage.last_resampled(&series)? };
+    let candles = storage.candles(source, from, None)?;
+    let (Some(first), Some(last)) = (candles.first(), candles.last()) else {
+        return Ok(0);
+    };
+
+    let mut bars: Vec<(StoredCandle, _)> = Vec::new();
+    for candle in &candles {
+        let (open, close) = alignment.bar(timeframe, candle.timestamp);
+        match bars.last_mut() {
+            Some((bar, _)) if bar.timestamp == open => {
+                bar.high = bar.high.max(candle.high);
+                bar.low = bar.low.min(candle.low);
+                bar.close = candle.close;
+                bar.volume += candle.volume;
+            }
+            _ => bars.push((StoredCandle { timestamp: open, ..*candle }, close)),
+        }
+    }
+
+    let stored_until = source_alignment.candle(source.granularity, last.timestamp).1;
+    if bars.last().is_some_and(|&(_, close)| close > stored_until) {
+        bars.pop();
+    }
+    let partial = |bar: &StoredCandle| source_alignment.candle(source.granularity, bar.timestamp).0 < first.timestamp;
+    if from.is_none() && bars.first().is_some_and(|(bar, _)| partial(bar)) {
+        bars.remove(0);
+    }
+
+    let bars: Vec<StoredCandle> = bars.into_iter().map(|(bar, _)| bar).collect();
+    storage.insert_resampled(&series, &bars)?;
+    Ok(bars.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candle::{Candle, Ohlc, PriceComponent};
+    use crate::storage::Retention;
+    use crate::test_util::{candle, storage_with, utc};
+    use chrono::{DateTime, Utc};
+    use rust_decimal::Decimal;
+
+    /// An H1 candle at `time` whose prices are its UTC hour: open `h`, high `h + 0.5`,
+    /// low `h - 0.5`, close `h + 0.25`.
+    fn hourly(time: DateTime<Utc>) -> Candle {
+        let hour = Decimal::from(time.format("%H").to_string().parse::<u32>().unwrap());
+        let price = |hundredths: i64| hour + Decimal::new(hundredths, 2);
+        candle(time, Ohlc { o: price(0), h: price(50), l: price(-50), c: price(25) })
+    }
+
+    fn hours(from: &str, count: i64) -> Vec<Candle> {
+        (0..count).map(|n| hourly(utc(from) + TimeDelta::hours(n))).collect()
+    }
+
+    #[test]
+    fn timeframes_parse_their_names() {
+        assert_eq!("4h".parse(), Ok(Timeframe::Hours(4)));
+        assert_eq!(" 2D ".parse(), Ok(Timeframe::Days(2)));
+        assert_eq!("6m".parse(), Ok(Timeframe::Months(6)));
+        assert_eq!("q".parse(), Ok(Timeframe::Months(3)));
+        assert_eq!("Y".parse(), Ok(Timeframe::Months(12)));
+        for invalid in ["0H", "121H", "7D", "5M", "H", "2W", "", "1.5H"] {
+            assert!(invalid.parse::<Timeframe>().is_err(), "{}", invalid);
+        }
+        for timeframe in [Timeframe::Hours(120), Timeframe::Days(6), Timeframe::Months(2), Timeframe::Months(3), Timeframe::Months(12)] {
+            assert_eq!(timeframe.name().parse(), Ok(timeframe));
+        }
+        assert_eq!(Timeframe::Months(12).name(), "Y");
+    }
+
+    #[test]
+    fn candles_fit_bars_they_divide() {
+        let default = Alignment::default();
+        let utc_days = Alignment::new(0, chrono_tz::UTC, default.weekly()).unwrap();
+        assert!(Timeframe::Hours(4).fits(Granularity::H1, default, default));
+        assert!(Timeframe::Hours(6).fits(Granularity::H3, default, default));
+        assert!(!Timeframe::Hours(4).fits(Granularity::H3, default, default));
+        assert!(!Timeframe::Hours(48).fits(Granularity::D, default, default));
+        assert!(Timeframe::Days(2).fits(Granularity::D, default, default));
+        assert!(!Timeframe::Days(2).fits(Granularity::W, default, default));
+        assert!(Timeframe::Months(3).fits(Granularity::M, default, default));
+        // Daily candles of another alignment straddle the bars' days.
+        assert!(!Timeframe::Days(2).fits(Granularity::D, default, utc_days));
+        assert!(Timeframe::Days(2).fits(Granularity::H1, default, utc_days));
+    }
+
+    #[test]
+    fn bars_aggregate_their_candles_and_skip_partial_ones() {
+        let source = SeriesKey::new("EUR_USD", Granularity::H1, PriceComponent::M);
+        // 4H bars open at 01:00, 05:00, 09:00 and 13:00 UTC on Monday. The first one
+        // misses its 01:00 candle and the last one ends after the 13:00 candle.
+        let mut storage = storage_with(&source, &hours("2026-10-12T02:00:00Z", 12));
+
+        let target = ResampledKey::new("EUR_USD", Timeframe::Hours(4), PriceComponent::M);
+        assert_eq!(resample(&mut storage, &source, Timeframe::Hours(4), Alignment::default(), false).unwrap(), 2);
+        let bar = |open: &str, hour: i64| StoredCandle {
+            timestamp: utc(open),
+            open: Decimal::from(hour),
+            high: Decimal::from(hour + 3) + Decimal::new(5, 1),
+            low: Decimal::from(hour) - Decimal::new(5, 1),
+            close: Decimal::from(hour + 3) + Decimal::new(25, 2),
+            volume: 4.0,
+        };
+        assert_eq!(
+            storage.resampled(&target, None, None).unwrap(),
+            [bar("2026-10-12T05:00:00Z", 5), bar("2026-10-12T09:00:00Z", 9)]
+        );
+
+        // Once its last candle is stored, the 13:00 bar is written on the next run.
+        storage.insert_candles(&source, &hours("2026-10-12T14:00:00Z", 3), Retention::All).unwrap();
+        assert_eq!(resample(&mut storage, &source, Timeframe::Hours(4), Alignment::default(), false).unwrap(), 2);
+        let bars = storage.resampled(&target, None, None).unwrap();
+        assert_eq!(bars.len(), 3);
+        assert_eq!(bars[2], bar("2026-10-12T13:00:00Z", 13));
+    }
+
+    #[test]
+    fn bars_of_another_alignment_are_refused() {
+        let source = SeriesKey::new("EUR_USD", Granularity::H1, PriceComponent::M);
+        let mut storage = storage_with(&source, &hours("2026-10-12T00:00:00Z", 72));
+
+        let utc_days = Alignment::new(0, chrono_tz::UTC, Alignment::default().weekly()).unwrap();
+        resample(&mut storage, &source, Timeframe::Days(2), Alignment::default(), false).unwrap();
+        assert!(matches!(
+            resample(&mut storage, &source, Timeframe::Days(2), utc_days, false),
+            Err(Error::AlignmentMismatch { .. })
+        ));
+    }
+}
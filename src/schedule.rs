@@ -0,0 +1,563 @@
+use crate::candle::Granularity;
+use crate::error::{Error, Result};
+use crate::resample::Timeframe;
+use chrono::{DateTime, Datelike, Days, LocalResult, Months, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono_tz::{America::New_York, Tz};
+use std::fmt;
+
+/// Hour in New York time at which the FX week ends on Friday and starts again on Sunday.
+const MARKET_HOUR: u32 = 17;
+
+/// Where OANDA starts days and weeks, which fixes when daily and longer candles, and
+/// the hourly ones that divide a day, open and close. The default is OANDA's own:
+/// 17:00 New York time, with weeks starting on Friday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alignment {
+    daily_hour: u32,
+    timezone: Tz,
+    weekly: Weekday,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Alignment { daily_hour: 17, timezone: New_York, weekly: Weekday::Fri }
+    }
+}
+
+impl fmt::Display for Alignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:00 {}, weeks from {}", self.daily_hour, self.timezone.name(), weekday_name(self.weekly))
+    }
+}
+
+impl Alignment {
+    /// Days starting at `daily_hour`, 0 to 23, in `timezone` and weeks on `weekly`.
+    pub fn new(daily_hour: u32, timezone: Tz, weekly: Weekday) -> Result<Alignment> {
+        if daily_hour >= 24 {
+            return Err(Error::Config(format!("daily alignment must be an hour from 0 to 23, got {}", daily_hour)));
+        }
+        Ok(Alignment { daily_hour, timezone, weekly })
+    }
+
+    /// Hour of the day at which days start.
+    pub fn daily_hour(&self) -> u32 {
+        self.daily_hour
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
+    /// Day on which weeks start.
+    pub fn weekly(&self) -> Weekday {
+        self.weekly
+    }
+
+    /// The part of `self` that affects candles of `granularity`, the rest left at
+    /// OANDA's default: nothing shorter than two hours depends on the alignment, and
+    /// only weekly candles on the weekday.
+    pub fn for_granularity(&self, granularity: Granularity) -> Alignment {
+        let default = Alignment::default();
+        match granularity {
+            Granularity::W => *self,
+            Granularity::H2 | Granularity::H3 | Granularity::H4 | Granularity::H6 | Granularity::H8 | Granularity::H12
+            | Granularity::D | Granularity::M => Alignment { weekly: default.weekly, ..*self },
+            _ => default,
+        }
+    }
+
+    /// The `dailyAlignment`, `alignmentTimezone` and `weeklyAlignment` query parameters
+    /// that differ from OANDA's default.
+    pub fn query(&self) -> Vec<(&'static str, String)> {
+        let default = Alignment::default();
+        let mut params = Vec::new();
+        if self.daily_hour != default.daily_hour {
+            params.push(("dailyAlignment", self.daily_hour.to_string()));
+        }
+        if self.timezone != default.timezone {
+            params.push(("alignmentTimezone", self.timezone.name().to_string()));
+        }
+        if self.weekly != default.weekly {
+            params.push(("weeklyAlignment", weekday_name(self.weekly).to_string()));
+        }
+        params
+    }
+
+    /// When the next candle of `granularity` after `after` closes.
+    pub fn next_close(&self, granularity: Granularity, after: DateTime<Utc>) -> DateTime<Utc> {
+        self.candle(granularity, after).1
+    }
+
+    /// Open and close of the candle of `granularity` that contains `at`. Candles that
+    /// would lie entirely within the weekend, when the FX market is shut, are skipped
+    /// in favour of the first one after it, as OANDA has none.
+    pub fn candle(&self, granularity: Granularity, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let (open, close) = self.bounds(granularity, at);
+        match weekend_reopen(open) {
+            Some(reopen) if close <= reopen => self.bounds(granularity, reopen),
+            _ => (open, close),
+        }
+    }
+
+    fn bounds(&self, granularity: Granularity, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let (day, start) = self.day_start(at);
+        let next_day = self.anchor(day + Days::new(1));
+
+        match granularity {
+            Granularity::D => (start, next_day),
+            Granularity::W => {
+                let open = (0..7)
+                    .map(|n| day - Days::new(n))
+                    .find(|d| d.weekday() == self.weekly)
+                    .expect("every week has the weekday");
+                (self.anchor(open), self.anchor(open + Days::new(7)))
+            }
+            Granularity::M => {
+                let first = self.month_of(at);
+                (self.month_start(first), self.month_start(first + Months::new(1)))
+            }
+            intraday => {
+                let period = intraday.duration().expect("intraday candles have a fixed length");
+                let elapsed = (at - start).num_seconds() / period.num_seconds();
+                let open = start + period * elapsed as i32;
+                // Periods that don't divide a DST-shortened day are cut short by the next one.
+                (open, (open + period).min(next_day))
+            }
+        }
+    }
+
+    /// The latest close of a candle of `granularity` at or before `now`.
+    pub fn last_close(&self, granularity: Granularity, now: DateTime<Utc>) -> DateTime<Utc> {
+        let mut at = self.bounds(granularity, now).0;
+        loop {
+            let (open, close) = self.bounds(granularity, at - TimeDelta::seconds(1));
+            match weekend_reopen(open) {
+                Some(reopen) if close <= reopen => at = weekend_close(open).expect("the candle opens in the weekend"),
+                _ => return close,
+            }
+        }
+    }
+
+    /// Roughly how many candles of `granularity` open after `after` and close by
+    /// `until`: exactly for daily and longer ones, and for shorter ones the time the
+    /// market is open in between divided by their length.
+    pub fn count_candles(&self, granularity: Granularity, after: DateTime<Utc>, until: DateTime<Utc>) -> usize {
+        let mut at = self.candle(granularity, after).1;
+        match granularity.duration().filter(|&length| length < TimeDelta::days(1)) {
+            Some(length) => {
+                let mut open = TimeDelta::zero();
+                while at < until {
+                    if let Some(reopen) = weekend_reopen(at) {
+                        at = reopen;
+                        continue;
+                    }
+                    let close = friday_close(week_open(at));
+                    open += close.min(until) - at;
+                    at = close;
+                }
+                (open.num_seconds() / length.num_seconds()) as usize
+            }
+            None => {
+                let mut count = 0;
+                loop {
+                    let (_, close) = self.candle(granularity, at);
+                    if close > until {
+                        return count;
+                    }
+                    count += 1;
+                    at = close;
+                }
+            }
+        }
+    }
+
+    /// Open and close of the bar of `timeframe` that contains `at`. Bars that would run
+    /// into the weekend close at the Friday close instead, when their last candle does.
+    pub fn bar(&self, timeframe: Timeframe, at: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        match timeframe {
+            Timeframe::Hours(hours) => {
+                let week = week_open(at);
+                let length = TimeDelta::hours(i64::from(hours));
+                let elapsed = (at - week).num_seconds() / length.num_seconds();
+                let open = week + length * elapsed as i32;
+                (open, (open + length).min(friday_close(week)))
+            }
+            Timeframe::Days(days) => {
+                let (day, _) = self.day_start(at);
+                // Weeks start with the day the Sunday open falls in, which in timezones
+                // ahead of New York begins before it.
+                let this_week = week_open(at);
+                let next_week = market_time(this_week.with_timezone(&New_York).date_naive() + Days::new(7));
+                let week = if self.day_start(next_week).0 <= day { next_week } else { this_week };
+                let first = self.day_start(week).0;
+                let elapsed = (day - first).num_days() as u64 / u64::from(days);
+                let open = first + Days::new(elapsed * u64::from(days));
+                (self.anchor(open), self.anchor(open + Days::new(days.into())).min(friday_close(week)))
+            }
+            Timeframe::Months(months) => {
+                let first = self.month_of(at);
+                let group = first.with_month0(first.month0() / months * months).expect("an earlier month of the year");
+                let close = self.month_start(group + Months::new(months));
+                (self.month_start(group), weekend_close(close).unwrap_or(close))
+            }
+        }
+    }
+
+    /// The start of the day `at` falls in, with the date it is named by.
+    fn day_start(&self, at: DateTime<Utc>) -> (NaiveDate, DateTime<Utc>) {
+        let date = at.with_timezone(&self.timezone).date_naive();
+        let start = self.anchor(date);
+        if start <= at {
+            (date, start)
+        } else {
+            let previous = date - Days::new(1);
+            (previous, self.anchor(previous))
+        }
+    }
+
+    /// `date` at the daily hour in the alignment timezone.
+    fn anchor(&self, date: NaiveDate) -> DateTime<Utc> {
+        let local = date.and_time(NaiveTime::from_hms_opt(self.daily_hour, 0, 0).expect("checked by Alignment::new"));
+        match self.timezone.from_local_datetime(&local) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+            // Skipped by a DST change or a change of offset, which can last hours or a
+            // whole day: the day starts once the clocks have moved on.
+            LocalResult::None => (1..)
+                .find_map(|quarters| self.timezone.from_local_datetime(&(local + TimeDelta::minutes(15 * quarters))).earliest())
+                .expect("clocks move on eventually")
+                .with_timezone(&Utc),
+        }
+    }
+
+    /// First day of the month `at` falls in.
+    fn month_of(&self, at: DateTime<Utc>) -> NaiveDate {
+        let first = at.with_timezone(&self.timezone).date_naive().with_day(1).expect("every month has a first");
+        [first + Months::new(1), first, first - Months::new(1)]
+            .into_iter()
+            .find(|&first| self.month_start(first) <= at)
+            .expect("a month starts within three")
+    }
+
+    /// Start of the month whose first day is `first`: the start of the trading day
+    /// named after it, which for an evening daily hour is on the day before.
+    fn month_start(&self, first: NaiveDate) -> DateTime<Utc> {
+        if self.daily_hour >= 12 {
+            self.anchor(first - Days::new(1))
+        } else {
+            self.anchor(first)
+        }
+    }
+}
+
+/// Full English name, as OANDA spells `weeklyAlignment`.
+pub fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// When trading resumes if `time` falls in the weekend, from Friday to Sunday
+/// 17:00 New York time.
+fn weekend_reopen(time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let local = time.with_timezone(&New_York);
+    let market = NaiveTime::from_hms_opt(MARKET_HOUR, 0, 0).expect("valid hour");
+    let days_to_sunday = match local.weekday() {
+        Weekday::Fri if local.time() >= market => 2,
+        Weekday::Sat => 1,
+        Weekday::Sun if local.time() < market => 0,
+        _ => return None,
+    };
+
+    Some(market_time(local.date_naive() + Days::new(days_to_sunday)))
+}
+
+/// The Friday close that starts the weekend `time` falls in, if it does.
+fn weekend_close(time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let reopen = weekend_reopen(time)?;
+    Some(market_time(reopen.with_timezone(&New_York).date_naive() - Days::new(2)))
+}
+
+/// The last Sunday open at or before `at`.
+fn week_open(at: DateTime<Utc>) -> DateTime<Utc> {
+    let local = at.with_timezone(&New_York);
+    let sunday = local.date_naive() - Days::new(local.weekday().num_days_from_sunday().into());
+    let open = market_time(sunday);
+    if open <= at { open } else { market_time(sunday - Days::new(7)) }
+}
+
+/// The close of the week that opened at `week_open`.
+fn friday_close(week_open: DateTime<Utc>) -> DateTime<Utc> {
+    market_time(week_open.with_timezone(&New_York).date_naive() + Days::new(5))
+}
+
+/// `date` at the hour the FX week ends and starts, which no DST change skips.
+fn market_time(date: NaiveDate) -> DateTime<Utc> {
+    let time = NaiveTime::from_hms_opt(MARKET_HOUR, 0, 0).expect("valid hour");
+    New_York.from_local_datetime(&date.and_time(time)).earliest().expect("17:00 always exists").with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::utc;
+
+    #[test]
+    fn hourly_candles_close_on_the_hour() {
+        let alignment = Alignment::default();
+        assert_eq!(alignment.next_close(Granularity::H1, utc("2026-10-14T10:30:00Z")), utc("2026-10-14T11:00:00Z"));
+        assert_eq!(alignment.next_close(Granularity::M15, utc("2026-10-14T10:30:00Z")), utc("2026-10-14T10:45:00Z"));
+    }
+
+    #[test]
+    fn candles_within_the_weekend_are_skipped() {
+        let alignment = Alignment::default();
+        // The week closes on Friday at 17:00 New York time and reopens on Sunday.
+        assert_eq!(alignment.next_close(Granularity::H1, utc("2026-10-16T20:30:00Z")), utc("2026-10-16T21:00:00Z"));
+        assert_eq!(alignment.next_close(Granularity::H1, utc("2026-10-16T21:00:00Z")), utc("2026-10-18T22:00:00Z"));
+        assert_eq!(
+            alignment.candle(Granularity::H1, utc("2026-10-17T12:00:00Z")),
+            (utc("2026-10-18T21:00:00Z"), utc("2026-10-18T22:00:00Z"))
+        );
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-17T12:00:00Z")),
+            (utc("2026-10-18T21:00:00Z"), utc("2026-10-19T21:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn daily_candles_start_at_the_new_york_close() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-13T21:00:00Z"), utc("2026-10-14T21:00:00Z"))
+        );
+        assert_eq!(alignment.next_close(Granularity::D, utc("2026-10-14T21:00:00Z")), utc("2026-10-15T21:00:00Z"));
+    }
+
+    #[test]
+    fn daily_candles_follow_new_york_daylight_saving() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-29T12:00:00Z")),
+            (utc("2026-10-28T21:00:00Z"), utc("2026-10-29T21:00:00Z"))
+        );
+        // Clocks go back on Sunday 1 November, so the week reopens an hour later in UTC.
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-31T12:00:00Z")),
+            (utc("2026-11-01T22:00:00Z"), utc("2026-11-02T22:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn a_daily_hour_skipped_by_dst_starts_the_day_after_the_change() {
+        let alignment = Alignment { daily_hour: 2, ..Alignment::default() };
+        // 02:00 doesn't exist in New York on 8 March 2026, so the day starts at 03:00 EDT.
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-03-08T12:00:00Z")),
+            (utc("2026-03-08T07:00:00Z"), utc("2026-03-09T06:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn a_daily_hour_skipped_by_longer_offset_changes_starts_once_the_clocks_move_on() {
+        // Troll moves from UTC to UTC+2 on 29 March 2026, skipping 01:00 and 02:00.
+        let troll = Alignment::new(1, chrono_tz::Antarctica::Troll, Weekday::Fri).unwrap();
+        assert_eq!(
+            troll.candle(Granularity::D, utc("2026-03-29T12:00:00Z")),
+            (utc("2026-03-29T01:00:00Z"), utc("2026-03-29T23:00:00Z"))
+        );
+        // Apia skipped 30 December 2011 entirely, moving from UTC-10 to UTC+14.
+        let apia = Alignment::new(0, chrono_tz::Pacific::Apia, Weekday::Fri).unwrap();
+        assert_eq!(
+            apia.candle(Granularity::D, utc("2011-12-30T05:00:00Z")),
+            (utc("2011-12-29T10:00:00Z"), utc("2011-12-30T10:00:00Z"))
+        );
+        assert_eq!(
+            apia.candle(Granularity::D, utc("2011-12-30T12:00:00Z")),
+            (utc("2011-12-30T10:00:00Z"), utc("2011-12-31T10:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn daily_hours_past_the_day_are_refused() {
+        assert!(Alignment::new(23, New_York, Weekday::Fri).is_ok());
+        assert!(matches!(Alignment::new(24, New_York, Weekday::Fri), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn hours_that_dont_divide_a_dst_shortened_day_are_cut_short() {
+        let alignment = Alignment { daily_hour: 0, timezone: chrono_tz::Europe::London, ..Alignment::default() };
+        // London springs forward on 29 March 2026, leaving the day 23 hours long.
+        assert_eq!(
+            alignment.candle(Granularity::H6, utc("2026-03-29T22:00:00Z")),
+            (utc("2026-03-29T18:00:00Z"), utc("2026-03-29T23:00:00Z"))
+        );
+        assert_eq!(
+            alignment.candle(Granularity::H6, utc("2026-03-30T01:00:00Z")),
+            (utc("2026-03-29T23:00:00Z"), utc("2026-03-30T05:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn utc_days_keep_the_sunday_candle() {
+        let alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, ..Alignment::default() };
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-14T00:00:00Z"), utc("2026-10-15T00:00:00Z"))
+        );
+        // Saturday lies within the weekend, Sunday only starts in it.
+        assert_eq!(
+            alignment.candle(Granularity::D, utc("2026-10-17T12:00:00Z")),
+            (utc("2026-10-18T00:00:00Z"), utc("2026-10-19T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn four_hour_candles_count_from_the_daily_hour() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.candle(Granularity::H4, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-14T09:00:00Z"), utc("2026-10-14T13:00:00Z"))
+        );
+        let utc_alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, ..Alignment::default() };
+        assert_eq!(
+            utc_alignment.candle(Granularity::H4, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-14T12:00:00Z"), utc("2026-10-14T16:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn weekly_candles_start_on_the_weekly_alignment() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.candle(Granularity::W, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-09T21:00:00Z"), utc("2026-10-16T21:00:00Z"))
+        );
+        let wednesday = Alignment { weekly: Weekday::Wed, ..Alignment::default() };
+        assert_eq!(
+            wednesday.candle(Granularity::W, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-07T21:00:00Z"), utc("2026-10-14T21:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn monthly_candles_start_on_the_evening_before_the_first() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.candle(Granularity::M, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-09-30T21:00:00Z"), utc("2026-10-31T21:00:00Z"))
+        );
+        assert_eq!(
+            alignment.candle(Granularity::M, utc("2026-10-31T22:00:00Z")),
+            (utc("2026-10-31T21:00:00Z"), utc("2026-11-30T22:00:00Z"))
+        );
+        let utc_alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, ..Alignment::default() };
+        assert_eq!(
+            utc_alignment.candle(Granularity::M, utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-01T00:00:00Z"), utc("2026-11-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn only_set_alignment_is_queried() {
+        assert!(Alignment::default().query().is_empty());
+        let alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, weekly: Weekday::Mon };
+        assert_eq!(
+            alignment.query(),
+            [
+                ("dailyAlignment", "0".to_string()),
+                ("alignmentTimezone", "UTC".to_string()),
+                ("weeklyAlignment", "Monday".to_string())
+            ]
+        );
+        assert_eq!(alignment.for_granularity(Granularity::H1), Alignment::default());
+        assert_eq!(alignment.for_granularity(Granularity::D), Alignment { weekly: Weekday::Fri, ..alignment });
+    }
+
+    #[test]
+    fn hour_bars_count_from_the_sunday_open() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.bar(Timeframe::Hours(4), utc("2026-10-12T02:30:00Z")),
+            (utc("2026-10-12T01:00:00Z"), utc("2026-10-12T05:00:00Z"))
+        );
+        // 120 hours leave one for the last 7H bar, which the Friday close cuts short.
+        assert_eq!(
+            alignment.bar(Timeframe::Hours(7), utc("2026-10-16T20:30:00Z")),
+            (utc("2026-10-16T20:00:00Z"), utc("2026-10-16T21:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn day_bars_count_from_the_first_day_of_the_week() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.bar(Timeframe::Days(2), utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-13T21:00:00Z"), utc("2026-10-15T21:00:00Z"))
+        );
+        let utc_alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, ..Alignment::default() };
+        assert_eq!(
+            utc_alignment.bar(Timeframe::Days(2), utc("2026-10-14T12:00:00Z")),
+            (utc("2026-10-13T00:00:00Z"), utc("2026-10-15T00:00:00Z"))
+        );
+        assert_eq!(
+            utc_alignment.bar(Timeframe::Days(2), utc("2026-10-16T12:00:00Z")),
+            (utc("2026-10-15T00:00:00Z"), utc("2026-10-16T21:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn month_bars_count_from_january() {
+        let alignment = Alignment::default();
+        assert_eq!(
+            alignment.bar(Timeframe::Months(3), utc("2026-05-10T12:00:00Z")),
+            (utc("2026-03-31T21:00:00Z"), utc("2026-06-30T21:00:00Z"))
+        );
+        assert_eq!(
+            alignment.bar(Timeframe::Months(12), utc("2026-05-10T12:00:00Z")),
+            (utc("2025-12-31T22:00:00Z"), utc("2026-12-31T22:00:00Z"))
+        );
+        // The quarter would end on Saturday 30 September 2028, in the weekend.
+        assert_eq!(
+            alignment.bar(Timeframe::Months(3), utc("2028-08-15T12:00:00Z")),
+            (utc("2028-06-30T21:00:00Z"), utc("2028-09-29T21:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn candle_counts_skip_the_weekend() {
+        let alignment = Alignment::default();
+        let count = |g, after, until| alignment.count_candles(g, utc(after), utc(until));
+        assert_eq!(count(Granularity::H1, "2026-10-14T10:00:00Z", "2026-10-14T15:00:00Z"), 4);
+        assert_eq!(count(Granularity::H1, "2026-10-14T10:00:00Z", "2026-10-14T14:59:00Z"), 3);
+        // One hour before the Friday close and two after the Sunday open.
+        assert_eq!(count(Granularity::H1, "2026-10-16T19:00:00Z", "2026-10-18T23:00:00Z"), 3);
+        assert_eq!(count(Granularity::M5, "2026-10-16T19:00:00Z", "2026-10-18T23:00:00Z"), 23 + 24);
+        assert_eq!(count(Granularity::D, "2026-10-12T21:00:00Z", "2026-10-16T21:00:00Z"), 3);
+        assert_eq!(count(Granularity::D, "2026-10-15T21:00:00Z", "2026-10-20T21:00:00Z"), 2);
+        assert_eq!(count(Granularity::M, "2026-06-30T21:00:00Z", "2026-10-16T00:00:00Z"), 2);
+    }
+
+    #[test]
+    fn last_close_is_before_the_forming_candle_and_the_weekend() {
+        let alignment = Alignment::default();
+        let last_close = |g, now| alignment.last_close(g, utc(now));
+        assert_eq!(last_close(Granularity::H1, "2026-10-14T10:30:00Z"), utc("2026-10-14T10:00:00Z"));
+        assert_eq!(last_close(Granularity::H1, "2026-10-14T10:00:00Z"), utc("2026-10-14T10:00:00Z"));
+        assert_eq!(last_close(Granularity::D, "2026-10-14T12:00:00Z"), utc("2026-10-13T21:00:00Z"));
+        for now in ["2026-10-17T12:00:00Z", "2026-10-18T21:30:00Z"] {
+            assert_eq!(last_close(Granularity::H1, now), utc("2026-10-16T21:00:00Z"), "{}", now);
+            assert_eq!(last_close(Granularity::D, now), utc("2026-10-16T21:00:00Z"), "{}", now);
+        }
+        // A UTC Friday runs into the weekend and closes on Saturday.
+        let utc_alignment = Alignment { daily_hour: 0, timezone: chrono_tz::UTC, ..Alignment::default() };
+        assert_eq!(utc_alignment.last_close(Granularity::D, utc("2026-10-18T12:00:00Z")), utc("2026-10-17T00:00:00Z"));
+        assert_eq!(utc_alignment.last_close(Granularity::D, utc("2026-10-19T12:00:00Z")), utc("2026-10-19T00:00:00Z"));
+    }
+}
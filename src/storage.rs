@@ -0,0 +1,1295 @@
+use crate::candle::{Candle, Granularity, PriceComponent};
+use crate::client::Instrument;
+use crate::error::{Error, Result};
+use crate::resample::Timeframe;
+use crate::schedule::{weekday_name, Alignment};
+use chrono::{DateTime, TimeDelta, Utc};
+use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row, Statement, ToSql};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How much history [`Storage::insert_candles`] keeps in a table after every insert.
+#[derive(Debug, Clone, Copy)]
+pub enum Retention {
+    All,
+    Bars(usize),
+    Window(TimeDelta),
+}
+
+impl FromStr for Retention {
+    type Err = String;
+
+    /// Parses `all`, a bar count (`2000`) or a window in hours, days, weeks or years (`90d`).
+    /// Zero is refused, as it would empty the table on every insert.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s == "all" {
+            return Ok(Retention::All);
+        }
+        if let Ok(bars) = s.parse::<usize>() {
+            if bars == 0 {
+                return Err("retention must keep at least one bar, use `all` to keep everything".to_string());
+            }
+            return Ok(Retention::Bars(bars));
+        }
+
+        let window = s.chars().last().and_then(|unit| {
+            let amount = i64::from(s[..s.len() - unit.len_utf8()].parse::<u32>().ok()?);
+            match unit {
+                'h' => TimeDelta::try_hours(amount),
+                'd' => TimeDelta::try_days(amount),
+                'w' => TimeDelta::try_weeks(amount),
+                'y' => TimeDelta::try_days(amount.checked_mul(365)?),
+                _ => None,
+            }
+        });
+        if window.is_some_and(|window| window.is_zero()) {
+            return Err(format!("retention window `{}` would keep nothing, use `all` to keep everything", s));
+        }
+        window
+            .map(Retention::Window)
+            .ok_or_else(|| format!("invalid retention `{}`, expected all, a bar count or a window like 90d", s))
+    }
+}
+
+/// Mid candles keep the plain `{instrument}_{granularity}` name so existing databases carry on.
+pub fn table_name(instrument: &str, granularity: Granularity, component: PriceComponent) -> String {
+    series_table(instrument, granularity.as_str(), component)
+}
+
+fn series_table(instrument: &str, period: &str, component: PriceComponent) -> String {
+    let base = format!("{}_{}", instrument.to_lowercase(), period);
+    match component {
+        PriceComponent::M => base,
+        PriceComponent::B => format!("{}_bid", base),
+        PriceComponent::A => format!("{}_ask", base),
+    }
+}
+
+/// Inverse of [`series_table`]: the instrument, period name and price component.
+fn split_table_name(name: &str) -> Option<(&str, &str, PriceComponent)> {
+    let (rest, component) = match name.rsplit_once('_')? {
+        (rest, "bid") => (rest, PriceComponent::B),
+        (rest, "ask") => (rest, PriceComponent::A),
+        _ => (name, PriceComponent::M),
+    };
+    let (instrument, period) = rest.rsplit_once('_')?;
+    if instrument.is_empty() || instrument != instrument.to_lowercase() {
+        return None;
+    }
+    Some((instrument, period, component))
+}
+
+/// How candles are laid out in the database. [`Storage::open`] detects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// One table per series, named by [`table_name`]. What every database starts with.
+    Tables,
+    /// A single `candles` table keyed by instrument, granularity, price type and
+    /// timestamp. Opted into with [`Storage::normalize`].
+    Normalized,
+}
+
+/// How prices are stored, fixed for a database once its first candle table exists.
+/// [`Storage::open`] detects it from the column types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PriceFormat {
+    /// `REAL`, the nearest `f64`. What databases start with.
+    #[default]
+    Real,
+    /// `INTEGER` counts of the smallest price step, see [`Storage::price_scale`].
+    Scaled,
+    /// `TEXT`, the decimal exactly as OANDA sent it.
+    Text,
+}
+
+impl PriceFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PriceFormat::Real => "real",
+            PriceFormat::Scaled => "scaled",
+            PriceFormat::Text => "text",
+        }
+    }
+
+    pub fn sql_type(&self) -> &'static str {
+        match self {
+            PriceFormat::Real => "REAL",
+            PriceFormat::Scaled => "INTEGER",
+            PriceFormat::Text => "TEXT",
+        }
+    }
+
+    fn from_sql_type(name: &str) -> Option<PriceFormat> {
+        [PriceFormat::Real, PriceFormat::Scaled, PriceFormat::Text].into_iter().find(|f| f.sql_type().eq_ignore_ascii_case(name))
+    }
+}
+
+/// One stored series: the candles of an instrument at one granularity and price component.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SeriesKey {
+    /// Instrument in OANDA's spelling, e.g. `EUR_USD`.
+    pub instrument: String,
+    pub granularity: Granularity,
+    pub component: PriceComponent,
+}
+
+impl SeriesKey {
+    pub fn new(instrument: &str, granularity: Granularity, component: PriceComponent) -> Self {
+        SeriesKey { instrument: instrument.to_uppercase(), granularity, component }
+    }
+
+    /// The series' [`table_name`], which also names it in messages and exports.
+    pub fn name(&self) -> String {
+        table_name(&self.instrument, self.granularity, self.component)
+    }
+
+    /// Inverse of [`table_name`], `None` for tables that hold no candles.
+    pub fn parse(name: &str) -> Option<SeriesKey> {
+        let (instrument, granularity, component) = split_table_name(name)?;
+        Some(SeriesKey::new(instrument, Granularity::parse(granularity)?, component))
+    }
+}
+
+/// A series of bars built by [`resample`](crate::resample()) from a stored series of the
+/// same instrument and price component. It is stored like one, in a table named
+/// `{instrument}_{timeframe}` with the same suffixes, or in `candles` under the
+/// timeframe's name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResampledKey {
+    pub instrument: String,
+    pub timeframe: Timeframe,
+    pub component: PriceComponent,
+}
+
+impl ResampledKey {
+    pub fn new(instrument: &str, timeframe: Timeframe, component: PriceComponent) -> Self {
+        ResampledKey { instrument: instrument.to_uppercase(), timeframe, component }
+    }
+
+    pub fn name(&self) -> String {
+        series_table(&self.instrument, &self.timeframe.name(), self.component)
+    }
+
+    /// Inverse of [`ResampledKey::name`].
+    pub fn parse(name: &str) -> Option<ResampledKey> {
+        let (instrument, period, component) = split_table_name(name)?;
+        let timeframe: Timeframe = period.parse().ok()?;
+        (timeframe.name() == period).then(|| ResampledKey::new(instrument, timeframe, component))
+    }
+}
+
+/// A row of a candle table. Prices are exact unless the database stores them as
+/// [`PriceFormat::Real`], and are serialized as numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StoredCandle {
+    /// Open time of the candle.
+    pub timestamp: DateTime<Utc>,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub open: Decimal,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub high: Decimal,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub low: Decimal,
+    #[serde(with = "rust_decimal::serde::float")]
+    pub close: Decimal,
+    pub volume: f64,
+}
+
+/// Size and extent of a stored series, see [`Storage::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeriesStats {
+    pub rows: usize,
+    /// Rows repeating an earlier timestamp, which only tables that
+    /// [`Storage::setup_series`] hasn't migrated yet can have.
+    pub duplicates: usize,
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+}
+
+/// Where the rows of one series live, so that each query below serves both layouts.
+struct Scope {
+    table: String,
+    /// Key columns besides `timestamp` and their parameters, empty for per-series tables.
+    columns: &'static str,
+    values: &'static str,
+    /// Condition selecting the series' rows.
+    filter: &'static str,
+}
+
+impl Scope {
+    /// Statement inserting or replacing the row with the key and `:timestamp`.
+    fn upsert(&self) -> String {
+        let Scope { table, columns, values, .. } = self;
+        format!(
+            "INSERT INTO {table} ({columns}timestamp, open, high, low, close, volume)
+             VALUES ({values}:timestamp, :open, :high, :low, :close, :volume)
+             ON CONFLICT ({columns}timestamp) DO UPDATE SET
+                open = excluded.open, high = excluded.high, low = excluded.low,
+                close = excluded.close, volume = excluded.volume;"
+        )
+    }
+}
+
+/// SQLite database holding the completed candles of every series, in either [`Layout`].
+pub struct Storage {
+    conn: Connection,
+    layout: Layout,
+    prices: PriceFormat,
+}
+
+impl Storage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Storage::with_connection(Connection::open(path)?)
+    }
+
+    /// Opens a database without ever writing to it, not even to create it: a missing
+    /// one reads as empty.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        let conn = if path.as_ref().exists() {
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?
+        } else {
+            Connection::open_in_memory()?
+        };
+        Storage::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        let mut storage = Storage { conn, layout: Layout::Tables, prices: PriceFormat::Real };
+        if storage.table_exists("candles")? {
+            storage.layout = Layout::Normalized;
+        }
+        storage.prices = storage.stored_price_format()?.unwrap_or_default();
+        Ok(storage)
+    }
+
+    pub fn price_format(&self) -> PriceFormat {
+        self.prices
+    }
+
+    /// Chooses how a database without candle tables will store prices. Fails for one
+    /// that already stores them another way.
+    pub fn set_price_format(&mut self, format: PriceFormat) -> Result<()> {
+        match self.stored_price_format()? {
+            Some(stored) if stored != format => Err(Error::Config(format!(
+                "the database already stores prices as `{}`, not `{}`",
+                stored.name(),
+                format.name()
+            ))),
+            _ => {
+                self.prices = format;
+                Ok(())
+            }
+        }
+    }
+
+    /// The type of the price columns of the first candle table, if there is one.
+    fn stored_price_format(&self) -> Result<Option<PriceFormat>> {
+        let tables: Vec<String> = match self.layout {
+            Layout::Normalized => vec!["candles".to_string()],
+            Layout::Tables => self.tables()?.iter().map(SeriesKey::name).chain(["live_candles".to_string()]).collect(),
+        };
+        for table in tables {
+            let column_type: Option<String> = self.conn
+                .query_row("SELECT type FROM pragma_table_info(?1) WHERE name = 'open'", [&table], |row| row.get(0))
+                .optional()?;
+            if let Some(column_type) = column_type {
+                return Ok(Some(PriceFormat::from_sql_type(&column_type).ok_or_else(|| {
+                    Error::Config(format!("unsupported price column type {} in {}", column_type, table))
+                })?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Number of decimals of the integers `instrument`'s prices are stored as in
+    /// [`PriceFormat::Scaled`]: one more than its `displayPrecision`, as a mid price can
+    /// fall halfway between two quotes. It is recorded the first time it is needed, so a
+    /// later change of `displayPrecision` cannot reinterpret stored prices.
+    pub fn price_scale(&mut self, instrument: &str) -> Result<u32> {
+        if let Some(scale) = self.stored_price_scale(instrument)? {
+            return Ok(scale);
+        }
+
+        let precision: Option<u32> = if self.table_exists("instruments")? {
+            self.conn
+                .query_row("SELECT display_precision FROM instruments WHERE name = ?1", [instrument], |row| row.get(0))
+                .optional()?
+        } else {
+            None
+        };
+        let Some(precision) = precision else {
+            return Err(Error::Config(format!(
+                "display precision of {} unknown, store the instruments before its candles",
+                instrument
+            )));
+        };
+
+        let scale = precision + 1;
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS price_scales (
+                instrument TEXT PRIMARY KEY,
+                scale INTEGER NOT NULL
+            );",
+        )?;
+        self.conn.execute("INSERT INTO price_scales (instrument, scale) VALUES (?1, ?2)", params![instrument, scale])?;
+        Ok(scale)
+    }
+
+    fn stored_price_scale(&self, instrument: &str) -> Result<Option<u32>> {
+        if !self.table_exists("price_scales")? {
+            return Ok(None);
+        }
+        Ok(self.conn
+            .query_row("SELECT scale FROM price_scales WHERE instrument = ?1", [instrument], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Converts OANDA's prices for `instrument` into column values.
+    fn price_encoder(&mut self, instrument: &str) -> Result<impl Fn(Decimal) -> Result<Value> + use<>> {
+        let format = self.prices;
+        let scale = match format {
+            PriceFormat::Scaled => self.price_scale(instrument)?,
+            _ => 0,
+        };
+        Ok(move |price: Decimal| encode_decimal(format, scale, price))
+    }
+
+    /// Scale to read `instrument`'s prices with, zero unless they are stored scaled.
+    fn read_scale(&self, instrument: &str) -> Result<u32> {
+        match self.prices {
+            PriceFormat::Scaled => self.stored_price_scale(instrument)?
+                .ok_or_else(|| Error::Config(format!("no price scale recorded for {}", instrument))),
+            _ => Ok(0),
+        }
+    }
+
+    fn table_exists(&self, name: &str) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+            [name],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Underlying connection, for queries the storage layer does not cover.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Whether the series stored in `table` in the per-series layout can have rows, which
+    /// it can't before that table is created.
+    fn holds(&self, table: &str) -> Result<bool> {
+        match self.layout {
+            Layout::Tables => self.table_exists(table),
+            Layout::Normalized => Ok(true),
+        }
+    }
+
+    /// Scope of the series stored in `table` in the per-series layout.
+    fn scope(&self, table: String) -> Scope {
+        match self.layout {
+            Layout::Tables => Scope { table, columns: "", values: "", filter: "1" },
+            Layout::Normalized => Scope {
+                table: "candles".to_string(),
+                columns: "instrument, granularity, price_type, ",
+                values: ":instrument, :granularity, :price_type, ",
+                filter: "instrument = :instrument AND granularity = :granularity AND price_type = :price_type",
+            },
+        }
+    }
+
+    /// Prepares the storage of `series` and migrates it to the current schema. Returns
+    /// the number of duplicate rows the migration removed.
+    pub fn setup_series(&mut self, series: &SeriesKey) -> Result<usize> {
+        if self.layout == Layout::Normalized {
+            return Ok(0);
+        }
+        self.create_table(&series.name())
+    }
+
+    /// Creates a per-series table unless it exists, then runs its migrations.
+    fn create_table(&mut self, table: &str) -> Result<usize> {
+        let price = self.prices.sql_type();
+        self.conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    timestamp INTEGER NOT NULL,
+                    open {price},
+                    high {price},
+                    low {price},
+                    close {price},
+                    volume REAL
+                );", table),
+            [],
+        )?;
+
+        self.migrate_unique_timestamp(table)
+    }
+
+    /// Tables created by older versions have no key and usually contain the last candle
+    /// of every run more than once. Keep the most recently inserted row per timestamp and
+    /// add the unique index that `insert_candles` upserts against. Runs once per table.
+    fn migrate_unique_timestamp(&mut self, table: &str) -> Result<usize> {
+        let index = format!("{}_timestamp", table);
+        let exists: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1)",
+            [&index],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(0);
+        }
+
+        let tx = self.conn.transaction()?;
+        let removed = tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE rowid NOT IN (SELECT MAX(rowid) FROM {} GROUP BY timestamp);",
+                table, table
+            ),
+            [],
+        )?;
+        tx.execute(&format!("CREATE UNIQUE INDEX {} ON {} (timestamp);", index, table), [])?;
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Switches the database to [`Layout::Normalized`]: creates the `candles` table and
+    /// moves every per-series table into it, in one transaction. Running it again moves
+    /// nothing. Returns the number of tables moved.
+    pub fn normalize(&mut self) -> Result<usize> {
+        let legacy = self.tables()?;
+        let resampled = self.resampled_tables()?;
+        let price = self.prices.sql_type();
+
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS candles (
+                instrument TEXT NOT NULL,
+                granularity TEXT NOT NULL,
+                price_type TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                open {price},
+                high {price},
+                low {price},
+                close {price},
+                volume REAL,
+                PRIMARY KEY (instrument, granularity, price_type, timestamp)
+            ) WITHOUT ROWID;
+            CREATE INDEX IF NOT EXISTS candles_granularity_timestamp ON candles (granularity, timestamp);"
+        ))?;
+        let tables = legacy.iter().map(|s| (s.name(), key_params(s)))
+            .chain(resampled.iter().map(|r| (r.name(), resampled_key_params(r))));
+        for (table, key) in tables {
+            // Later rows win, as in `migrate_unique_timestamp`, for tables that never got there.
+            let mut stmt = tx.prepare(&format!(
+                "INSERT OR REPLACE INTO candles (instrument, granularity, price_type, timestamp, open, high, low, close, volume)
+                 SELECT :instrument, :granularity, :price_type, timestamp, open, high, low, close, volume
+                 FROM {} ORDER BY rowid;",
+                table
+            ))?;
+            bind(&mut stmt, &key)?;
+            stmt.raw_execute()?;
+            tx.execute(&format!("DROP TABLE {};", table), [])?;
+        }
+        tx.commit()?;
+
+        self.layout = Layout::Normalized;
+        Ok(legacy.len() + resampled.len())
+    }
+
+    /// Upserts the completed candles that carry the series' price component, then
+    /// applies `retention`.
+    pub fn insert_candles(&mut self, series: &SeriesKey, candles: &[Candle], retention: Retention) -> Result<()> {
+        let scope = self.scope(series.name());
+        let key = key_params(series);
+        let encode = self.price_encoder(&series.instrument)?;
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut upsert = tx.prepare(&scope.upsert())?;
+            for candle in candles {
+                if let (true, Some(ohlc)) = (candle.complete, candle.ohlc(series.component)) {
+                    bind(&mut upsert, &key)?;
+                    bind(&mut upsert, &[
+                        (":timestamp", &candle.time.timestamp()),
+                        (":open", &encode(ohlc.o)?),
+                        (":high", &encode(ohlc.h)?),
+                        (":low", &encode(ohlc.l)?),
+                        (":close", &encode(ohlc.c)?),
+                        (":volume", &candle.volume),
+                    ])?;
+                    upsert.raw_execute()?;
+                }
+            }
+
+            let Scope { table, filter, .. } = scope;
+            let prune = match retention {
+                Retention::All => None,
+                Retention::Bars(keep) => Some((
+                    format!(
+                        "DELETE FROM {table} WHERE {filter} AND timestamp IN
+                            (SELECT timestamp FROM {table} WHERE {filter} ORDER BY timestamp DESC LIMIT -1 OFFSET :keep);"
+                    ),
+                    i64::try_from(keep).unwrap_or(i64::MAX),
+                )),
+                Retention::Window(window) => Some((
+                    format!("DELETE FROM {table} WHERE {filter} AND timestamp < :cutoff;"),
+                    (Utc::now() - window).timestamp(),
+                )),
+            };
+            if let Some((sql, bound)) = prune {
+                let mut stmt = tx.prepare(&sql)?;
+                bind(&mut stmt, &key)?;
+                bind(&mut stmt, &[(":keep", &bound), (":cutoff", &bound)])?;
+                stmt.raw_execute()?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replaces the forming candle of `series` in `live_candles`, away from the completed
+    /// history. A complete candle, or one without the series' price component, clears
+    /// the slot instead, so it never shows a bar that has since closed.
+    pub fn store_live(&mut self, series: &SeriesKey, candle: Option<&Candle>) -> Result<()> {
+        let price = self.prices.sql_type();
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS live_candles (
+                instrument TEXT NOT NULL,
+                granularity TEXT NOT NULL,
+                price_type TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                open {price},
+                high {price},
+                low {price},
+                close {price},
+                volume REAL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (instrument, granularity, price_type)
+            ) WITHOUT ROWID;"
+        ))?;
+
+        let forming = candle.filter(|c| !c.complete).and_then(|c| Some((c, c.ohlc(series.component)?)));
+        let Some((candle, ohlc)) = forming else {
+            self.conn.execute(
+                "DELETE FROM live_candles WHERE instrument = ?1 AND granularity = ?2 AND price_type = ?3",
+                params![series.instrument, series.granularity, series.component],
+            )?;
+            return Ok(());
+        };
+
+        let encode = self.price_encoder(&series.instrument)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO live_candles
+                (instrument, granularity, price_type, timestamp, open, high, low, close, volume, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                series.instrument,
+                series.granularity,
+                series.component,
+                candle.time.timestamp(),
+                encode(ohlc.o)?,
+                encode(ohlc.h)?,
+                encode(ohlc.l)?,
+                encode(ohlc.c)?,
+                candle.volume,
+                Utc::now().timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The forming candle of `series` as of the last sync that stored one.
+    pub fn live_candle(&self, series: &SeriesKey) -> Result<Option<StoredCandle>> {
+        if !self.table_exists("live_candles")? {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT timestamp, open, high, low, close, volume FROM live_candles
+             WHERE instrument = ?1 AND granularity = ?2 AND price_type = ?3",
+        )?;
+        let scale = self.read_scale(&series.instrument)?;
+        let mut rows = stmt.query(params![series.instrument, series.granularity, series.component])?;
+        rows.next()?.map(|row| stored_candle(row, scale)).transpose()
+    }
+
+    /// Records the alignment `series` is built with, or fails if its candles have
+    /// another. Only the part of `alignment` that affects the series' granularity
+    /// counts, and series stored before alignments were recorded have OANDA's default.
+    pub fn check_alignment(&mut self, series: &SeriesKey, alignment: Alignment) -> Result<()> {
+        let stored = self.compatible_alignment(series, alignment)?;
+        self.record_alignment(&series.instrument, &series.granularity, series.component, stored)
+    }
+
+    /// Fails as [`Storage::check_alignment`] would, but records nothing. Returns the
+    /// part of `alignment` that counts for `series`.
+    pub fn compatible_alignment(&self, series: &SeriesKey, alignment: Alignment) -> Result<Alignment> {
+        let requested = alignment.for_granularity(series.granularity);
+        let stored = match self.alignment(series)? {
+            Some(stored) => stored,
+            None if self.first_timestamp(series)?.is_some() => Alignment::default(),
+            None => requested,
+        };
+        if stored != requested {
+            return Err(Error::AlignmentMismatch { series: series.name(), stored, requested });
+        }
+        Ok(stored)
+    }
+
+    /// Records the alignment bars of `series` are built with, or fails if they have another.
+    pub fn check_resampled_alignment(&mut self, series: &ResampledKey, alignment: Alignment) -> Result<()> {
+        match self.alignment_row(&series.instrument, &series.timeframe, series.component)? {
+            Some(stored) if stored != alignment => {
+                Err(Error::AlignmentMismatch { series: series.name(), stored, requested: alignment })
+            }
+            _ => self.record_alignment(&series.instrument, &series.timeframe, series.component, alignment),
+        }
+    }
+
+    fn record_alignment(&mut self, instrument: &str, granularity: &dyn ToSql, component: PriceComponent, alignment: Alignment) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS series_alignment (
+                instrument TEXT NOT NULL,
+                granularity TEXT NOT NULL,
+                price_type TEXT NOT NULL,
+                daily_alignment INTEGER NOT NULL,
+                alignment_timezone TEXT NOT NULL,
+                weekly_alignment TEXT NOT NULL,
+                PRIMARY KEY (instrument, granularity, price_type)
+            ) WITHOUT ROWID;",
+        )?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO series_alignment
+                (instrument, granularity, price_type, daily_alignment, alignment_timezone, weekly_alignment)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                instrument,
+                granularity,
+                component,
+                alignment.daily_hour(),
+                alignment.timezone().name(),
+                weekday_name(alignment.weekly())
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The alignment recorded for `series` by [`Storage::check_alignment`].
+    pub fn alignment(&self, series: &SeriesKey) -> Result<Option<Alignment>> {
+        self.alignment_row(&series.instrument, &series.granularity, series.component)
+    }
+
+    fn alignment_row(&self, instrument: &str, granularity: &dyn ToSql, component: PriceComponent) -> Result<Option<Alignment>> {
+        if !self.table_exists("series_alignment")? {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT daily_alignment, alignment_timezone, weekly_alignment FROM series_alignment
+             WHERE instrument = ?1 AND granularity = ?2 AND price_type = ?3",
+        )?;
+        let mut rows = stmt.query(params![instrument, granularity, component])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+
+        let timezone: String = row.get(1)?;
+        let weekly: String = row.get(2)?;
+        Alignment::new(
+            row.get(0)?,
+            timezone.parse().map_err(|_| Error::Config(format!("unknown alignment timezone `{}`", timezone)))?,
+            weekly.parse().map_err(|_| Error::Config(format!("unknown weekly alignment `{}`", weekly)))?,
+        )
+        .map(Some)
+    }
+
+    /// Records ranges in which OANDA has no candles of `granularity` for `instrument`,
+    /// so that gap detection skips them.
+    pub fn record_closures(&mut self, instrument: &str, granularity: Granularity, closures: &[(DateTime<Utc>, DateTime<Utc>)]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS known_closures (
+                instrument TEXT NOT NULL,
+                granularity TEXT NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER NOT NULL,
+                PRIMARY KEY (instrument, granularity, start_time)
+            ) WITHOUT ROWID;",
+        )?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO known_closures (instrument, granularity, start_time, end_time) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (start, end) in closures {
+                stmt.execute(params![instrument, granularity, start.timestamp(), end.timestamp()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The ranges recorded by [`Storage::record_closures`], in order.
+    pub fn closures(&self, instrument: &str, granularity: Granularity) -> Result<Vec<(DateTime<Utc>, DateTime<Utc>)>> {
+        if !self.table_exists("known_closures")? {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT start_time, end_time FROM known_closures
+             WHERE instrument = ?1 AND granularity = ?2 ORDER BY start_time",
+        )?;
+        let mut rows = stmt.query(params![instrument, granularity])?;
+        let mut closures = Vec::new();
+        while let Some(row) = rows.next()? {
+            closures.push((from_timestamp(row.get(0)?)?, from_timestamp(row.get(1)?)?));
+        }
+        Ok(closures)
+    }
+
+    /// Saves the account's instrument metadata into `instruments`, and appends every
+    /// instrument that is new or whose metadata changed to `instruments_history`.
+    /// Returns the number of history rows added.
+    pub fn store_instruments(&mut self, instruments: &[Instrument]) -> Result<usize> {
+        const COLUMNS: &str = "name, type, display_name, pip_location, display_precision, trade_units_precision,
+            minimum_trade_size, maximum_trailing_stop_distance, minimum_trailing_stop_distance,
+            maximum_position_size, maximum_order_units, margin_rate";
+        const VALUES: &str = "?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12";
+
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS instruments (
+                name TEXT PRIMARY KEY,
+                type TEXT NOT NULL,
+                display_name TEXT NOT NULL,
+                pip_location INTEGER NOT NULL,
+                display_precision INTEGER NOT NULL,
+                trade_units_precision INTEGER NOT NULL,
+                minimum_trade_size TEXT NOT NULL,
+                maximum_trailing_stop_distance TEXT NOT NULL,
+                minimum_trailing_stop_distance TEXT NOT NULL,
+                maximum_position_size TEXT NOT NULL,
+                maximum_order_units TEXT NOT NULL,
+                margin_rate TEXT NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS instruments_history AS SELECT {}, updated_at AS valid_from FROM instruments WHERE 0;
+            CREATE UNIQUE INDEX IF NOT EXISTS instruments_history_name ON instruments_history (name, valid_from);",
+            COLUMNS
+        ))?;
+
+        let now = Utc::now().timestamp();
+        let mut changed = 0;
+        for i in instruments {
+            let values = params![
+                i.name, i.instrument_type, i.display_name, i.pip_location, i.display_precision,
+                i.trade_units_precision, i.minimum_trade_size, i.maximum_trailing_stop_distance,
+                i.minimum_trailing_stop_distance, i.maximum_position_size, i.maximum_order_units,
+                i.margin_rate, now
+            ];
+
+            let unchanged: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM instruments WHERE name = ?1 AND type = ?2 AND display_name = ?3
+                    AND pip_location = ?4 AND display_precision = ?5 AND trade_units_precision = ?6
+                    AND minimum_trade_size = ?7 AND maximum_trailing_stop_distance = ?8
+                    AND minimum_trailing_stop_distance = ?9 AND maximum_position_size = ?10
+                    AND maximum_order_units = ?11 AND margin_rate = ?12)",
+                &values[..12],
+                |row| row.get(0),
+            )?;
+            if !unchanged {
+                tx.execute(
+                    &format!("INSERT OR REPLACE INTO instruments_history ({}, valid_from) VALUES ({}, ?13);", COLUMNS, VALUES),
+                    values,
+                )?;
+                changed += 1;
+            }
+
+            tx.execute(
+                &format!("INSERT OR REPLACE INTO instruments ({}, updated_at) VALUES ({}, ?13);", COLUMNS, VALUES),
+                values,
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Every series in the database, sorted by name.
+    pub fn series(&self) -> Result<Vec<SeriesKey>> {
+        if self.layout == Layout::Tables {
+            return self.tables();
+        }
+
+        let mut series = Vec::new();
+        for (instrument, granularity, price_type) in self.normalized_keys()? {
+            if let (Some(granularity), Some(component)) = (Granularity::parse(&granularity), PriceComponent::from_name(&price_type)) {
+                series.push(SeriesKey::new(&instrument, granularity, component));
+            }
+        }
+        series.sort_by_key(|s| s.name());
+        Ok(series)
+    }
+
+    /// Distinct instrument, granularity and price type of the `candles` table.
+    fn normalized_keys(&self) -> Result<Vec<(String, String, String)>> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT instrument, granularity, price_type FROM candles")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Series stored in per-series tables.
+    fn tables(&self) -> Result<Vec<SeriesKey>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(names.iter().filter_map(|name| SeriesKey::parse(name)).collect())
+    }
+
+    /// Candles of `series` that opened at or after `from` and before `to`, oldest first.
+    pub fn candles(&self, series: &SeriesKey, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<StoredCandle>> {
+        self.rows(series.name(), &key_params(series), &series.instrument, from, to)
+    }
+
+    fn rows(&self, table: String, key: &[(&str, &dyn ToSql)], instrument: &str, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<StoredCandle>> {
+        if !self.holds(&table)? {
+            return Ok(Vec::new());
+        }
+        let Scope { table, filter, .. } = self.scope(table);
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT timestamp, open, high, low, close, volume FROM {table}
+             WHERE {filter} AND timestamp >= :from AND timestamp < :to ORDER BY timestamp"
+        ))?;
+        bind(&mut stmt, key)?;
+        bind(&mut stmt, &[
+            (":from", &from.map_or(i64::MIN, |t| t.timestamp())),
+            (":to", &to.map_or(i64::MAX, |t| t.timestamp())),
+        ])?;
+
+        let scale = self.read_scale(instrument)?;
+        let mut rows = stmt.raw_query();
+        let mut candles = Vec::new();
+        while let Some(row) = rows.next()? {
+            candles.push(stored_candle(row, scale)?);
+        }
+        Ok(candles)
+    }
+
+    /// Open times of the stored candles of `series`, oldest first, without reading their prices.
+    pub fn timestamps(&self, series: &SeriesKey) -> Result<Vec<DateTime<Utc>>> {
+        let table = series.name();
+        if !self.holds(&table)? {
+            return Ok(Vec::new());
+        }
+        let Scope { table, filter, .. } = self.scope(table);
+        let mut stmt = self.conn.prepare(&format!("SELECT timestamp FROM {table} WHERE {filter} ORDER BY timestamp"))?;
+        bind(&mut stmt, &key_params(series))?;
+
+        let mut rows = stmt.raw_query();
+        let mut timestamps = Vec::new();
+        while let Some(row) = rows.next()? {
+            timestamps.push(from_timestamp(row.get(0)?)?);
+        }
+        Ok(timestamps)
+    }
+
+    /// Open time of the oldest stored candle.
+    pub fn first_timestamp(&self, series: &SeriesKey) -> Result<Option<DateTime<Utc>>> {
+        self.stored_timestamp(series.name(), &key_params(series), "ASC")
+    }
+
+    /// Open time of the newest stored candle.
+    pub fn last_timestamp(&self, series: &SeriesKey) -> Result<Option<DateTime<Utc>>> {
+        self.stored_timestamp(series.name(), &key_params(series), "DESC")
+    }
+
+    fn stored_timestamp(&self, table: String, key: &[(&str, &dyn ToSql)], order: &str) -> Result<Option<DateTime<Utc>>> {
+        if !self.holds(&table)? {
+            return Ok(None);
+        }
+        let Scope { table, filter, .. } = self.scope(table);
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT timestamp FROM {table} WHERE {filter} ORDER BY timestamp {order} LIMIT 1"
+        ))?;
+        bind(&mut stmt, key)?;
+
+        let mut rows = stmt.raw_query();
+        rows.next()?.map(|row| from_timestamp(row.get(0)?)).transpose()
+    }
+
+    /// Counts the rows of `series` and finds its first and last timestamp.
+    pub fn stats(&self, series: &SeriesKey) -> Result<SeriesStats> {
+        self.table_stats(series.name(), &key_params(series))
+    }
+
+    /// [`Storage::stats`] for bars built by [`resample`](crate::resample()).
+    pub fn resampled_stats(&self, series: &ResampledKey) -> Result<SeriesStats> {
+        self.table_stats(series.name(), &resampled_key_params(series))
+    }
+
+    fn table_stats(&self, table: String, key: &[(&str, &dyn ToSql)]) -> Result<SeriesStats> {
+        if !self.holds(&table)? {
+            return Ok(SeriesStats::default());
+        }
+        let Scope { table, filter, .. } = self.scope(table);
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT COUNT(*), COUNT(DISTINCT timestamp), MIN(timestamp), MAX(timestamp) FROM {table} WHERE {filter}"
+        ))?;
+        bind(&mut stmt, key)?;
+
+        let mut rows = stmt.raw_query();
+        let row = rows.next()?.expect("aggregates return a row");
+        let (count, distinct): (usize, usize) = (row.get(0)?, row.get(1)?);
+        Ok(SeriesStats {
+            rows: count,
+            duplicates: count - distinct,
+            first: row.get::<_, Option<i64>>(2)?.map(from_timestamp).transpose()?,
+            last: row.get::<_, Option<i64>>(3)?.map(from_timestamp).transpose()?,
+        })
+    }
+
+    /// Upserts bars built by [`resample`](crate::resample()), creating their table first
+    /// in the per-series layout.
+    pub fn insert_resampled(&mut self, series: &ResampledKey, bars: &[StoredCandle]) -> Result<()> {
+        if self.layout == Layout::Tables {
+            self.create_table(&series.name())?;
+        }
+        let scope = self.scope(series.name());
+        let key = resampled_key_params(series);
+        let format = self.prices;
+        let scale = match format {
+            PriceFormat::Scaled => self.price_scale(&series.instrument)?,
+            _ => 0,
+        };
+        let tx = self.conn.transaction()?;
+
+        {
+            let mut upsert = tx.prepare(&scope.upsert())?;
+            for bar in bars {
+                bind(&mut upsert, &key)?;
+                bind(&mut upsert, &[
+                    (":timestamp", &bar.timestamp.timestamp()),
+                    (":open", &encode_decimal(format, scale, bar.open)?),
+                    (":high", &encode_decimal(format, scale, bar.high)?),
+                    (":low", &encode_decimal(format, scale, bar.low)?),
+                    (":close", &encode_decimal(format, scale, bar.close)?),
+                    (":volume", &bar.volume),
+                ])?;
+                upsert.raw_execute()?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Bars of `series` that opened at or after `from` and before `to`, oldest first.
+    pub fn resampled(&self, series: &ResampledKey, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Result<Vec<StoredCandle>> {
+        self.rows(series.name(), &resampled_key_params(series), &series.instrument, from, to)
+    }
+
+    /// Open time of the newest stored bar.
+    pub fn last_resampled(&self, series: &ResampledKey) -> Result<Option<DateTime<Utc>>> {
+        self.stored_timestamp(series.name(), &resampled_key_params(series), "DESC")
+    }
+
+    /// Every resampled series in the database, sorted by name.
+    pub fn resampled_series(&self) -> Result<Vec<ResampledKey>> {
+        if self.layout == Layout::Tables {
+            return self.resampled_tables();
+        }
+
+        let mut series = Vec::new();
+        for (instrument, timeframe, price_type) in self.normalized_keys()? {
+            if let (Ok(timeframe), Some(component)) = (timeframe.parse(), PriceComponent::from_name(&price_type)) {
+                series.push(ResampledKey::new(&instrument, timeframe, component));
+            }
+        }
+        series.sort_by_key(|s| s.name());
+        Ok(series)
+    }
+
+    fn resampled_tables(&self) -> Result<Vec<ResampledKey>> {
+        let mut stmt = self.conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+
+        Ok(names.iter().filter_map(|name| ResampledKey::parse(name)).collect())
+    }
+}
+
+impl ToSql for Granularity {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl ToSql for Timeframe {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.name().into())
+    }
+}
+
+impl ToSql for PriceComponent {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.name().into())
+    }
+}
+
+/// Values of the normalized layout's key columns.
+fn key_params(series: &SeriesKey) -> [(&'static str, &dyn ToSql); 3] {
+    [
+        (":instrument", &series.instrument),
+        (":granularity", &series.granularity),
+        (":price_type", &series.component),
+    ]
+}
+
+fn resampled_key_params(series: &ResampledKey) -> [(&'static str, &dyn ToSql); 3] {
+    [
+        (":instrument", &series.instrument),
+        (":granularity", &series.timeframe),
+        (":price_type", &series.component),
+    ]
+}
+
+/// Binds those of the named `params` that `stmt` uses, as the same parameters serve
+/// the per-series tables, which have no key columns, and the normalized table.
+fn bind(stmt: &mut Statement, params: &[(&str, &dyn ToSql)]) -> Result<()> {
+    for (name, value) in params {
+        if let Some(index) = stmt.parameter_index(name)? {
+            stmt.raw_bind_parameter(index, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads `timestamp, open, high, low, close, volume`, with scaled prices in units of
+/// ten to the minus `scale`.
+fn stored_candle(row: &Row, scale: u32) -> Result<StoredCandle> {
+    Ok(StoredCandle {
+        timestamp: from_timestamp(row.get(0)?)?,
+        open: decode_price(row.get_ref(1)?, scale)?,
+        high: decode_price(row.get_ref(2)?, scale)?,
+        low: decode_price(row.get_ref(3)?, scale)?,
+        close: decode_price(row.get_ref(4)?, scale)?,
+        volume: row.get(5)?,
+    })
+}
+
+fn from_timestamp(ts: i64) -> Result<DateTime<Utc>> {
+    DateTime::from_timestamp(ts, 0).ok_or(Error::Db(rusqlite::Error::IntegralValueOutOfRange(0, ts)))
+}
+
+/// A price as [`PriceFormat`] stores it. Scaled prices with more decimals than `scale`
+/// are refused rather than rounded.
+fn encode_decimal(format: PriceFormat, scale: u32, price: Decimal) -> Result<Value> {
+    Ok(match format {
+        PriceFormat::Real => Value::Real(price.to_f64().ok_or_else(|| Error::Price(price.to_string()))?),
+        PriceFormat::Scaled => {
+            // Decoded prices are normalized, so only the significant decimals count.
+            let price = price.normalize();
+            if price.scale() > scale {
+                return Err(Error::Price(price.to_string()));
+            }
+            let mut scaled = price;
+            scaled.rescale(scale);
+            Value::Integer(i64::try_from(scaled.mantissa()).map_err(|_| Error::Price(price.to_string()))?)
+        }
+        PriceFormat::Text => Value::Text(price.to_string()),
+    })
+}
+
+fn decode_price(value: ValueRef, scale: u32) -> Result<Decimal> {
+    let price = match value {
+        ValueRef::Real(price) => Decimal::from_f64(price),
+        ValueRef::Integer(units) => Some(Decimal::new(units, scale).normalize()),
+        ValueRef::Text(text) => std::str::from_utf8(text).ok().and_then(|text| text.parse().ok()),
+        ValueRef::Null | ValueRef::Blob(_) => None,
+    };
+    price.ok_or_else(|| Error::Price(format!("{:?}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{candle, instrument, ohlc, price, store};
+
+    #[test]
+    fn retention_parses_counts_and_windows() {
+        assert!(matches!("all".parse(), Ok(Retention::All)));
+        assert!(matches!("2000".parse(), Ok(Retention::Bars(2000))));
+        assert!(matches!("90D".parse(), Ok(Retention::Window(w)) if w == TimeDelta::days(90)));
+        assert!(matches!("5y".parse(), Ok(Retention::Window(w)) if w == TimeDelta::days(5 * 365)));
+        assert!("12x".parse::<Retention>().is_err());
+    }
+
+    #[test]
+    fn retention_refuses_to_keep_nothing() {
+        assert!("0".parse::<Retention>().is_err());
+        assert!("0d".parse::<Retention>().is_err());
+    }
+
+    #[test]
+    fn scaled_prices_count_steps_of_the_scale() {
+        assert_eq!(encode_decimal(PriceFormat::Scaled, 6, price("1.08545")).unwrap(), Value::Integer(1_085_450));
+        assert_eq!(encode_decimal(PriceFormat::Scaled, 6, price("1.085450")).unwrap(), Value::Integer(1_085_450));
+        assert_eq!(encode_decimal(PriceFormat::Scaled, 4, price("5432.1")).unwrap(), Value::Integer(54_321_000));
+        assert!(matches!(encode_decimal(PriceFormat::Scaled, 6, price("1.0854501")), Err(Error::Price(_))));
+    }
+
+    #[test]
+    fn prices_decode_to_what_was_encoded() {
+        for format in [PriceFormat::Real, PriceFormat::Scaled, PriceFormat::Text] {
+            for value in ["1.08545", "0.000123", "157.912", "38250.5"] {
+                let encoded = encode_decimal(format, 6, price(value)).unwrap();
+                assert_eq!(decode_price(ValueRef::from(&encoded), 6).unwrap(), price(value), "{} as {}", value, format.name());
+            }
+        }
+        assert!(decode_price(ValueRef::Null, 6).is_err());
+    }
+
+    #[test]
+    fn scaled_candles_round_trip_through_the_database() {
+        let mut storage = Storage::open(":memory:").unwrap();
+        storage.set_price_format(PriceFormat::Scaled).unwrap();
+        storage.store_instruments(&[instrument("EUR_USD")]).unwrap();
+
+        let series = SeriesKey::new("EUR_USD", Granularity::H1, PriceComponent::M);
+        let time = DateTime::from_timestamp(1_791_367_200, 0).unwrap();
+        let candle = Candle { volume: 42.0, ..candle(time, ohlc("1.08545", "1.08610", "1.08500", "1.085905")) };
+        store(&mut storage, &series, &[candle]);
+
+        assert_eq!(storage.price_scale("EUR_USD").unwrap(), 6);
+        assert_eq!(
+            storage.candles(&series, None, None).unwrap(),
+            [StoredCandle {
+                timestamp: time,
+                open: price("1.08545"),
+                high: price("1.0861"),
+                low: price("1.085"),
+                close: price("1.085905"),
+                volume: 42.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn instrument_history_grows_only_when_metadata_changes() {
+        let mut storage = Storage::open(":memory:").unwrap();
+        let history = |storage: &Storage| -> Vec<(String, String)> {
+            let mut stmt = storage.connection().prepare("SELECT name, margin_rate FROM instruments_history ORDER BY valid_from").unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|row| row.unwrap()).collect()
+        };
+        let eur_usd = Instrument { margin_rate: "0.0333".to_string(), ..instrument("EUR_USD") };
+        let xau_usd = instrument("XAU_USD");
+
+        assert_eq!(storage.store_instruments(&[eur_usd.clone(), xau_usd.clone()]).unwrap(), 2);
+        assert_eq!(storage.store_instruments(&[eur_usd.clone(), xau_usd.clone()]).unwrap(), 0);
+        assert_eq!(history(&storage).len(), 2);
+
+        // Changes are told apart by the second they were seen in.
+        storage.connection().execute("UPDATE instruments_history SET valid_from = valid_from - 3600", []).unwrap();
+        let changed = Instrument { margin_rate: "0.05".to_string(), ..eur_usd };
+        assert_eq!(storage.store_instruments(&[changed, xau_usd]).unwrap(), 1);
+        let history = history(&storage);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2], ("EUR_USD".to_string(), "0.05".to_string()));
+        let current: String = storage.connection()
+            .query_row("SELECT margin_rate FROM instruments WHERE name = 'EUR_USD'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(current, "0.05");
+    }
+
+    /// A table as older versions created it: no key, and the same candle stored by
+    /// several runs with a different close each time.
+    fn legacy_table(storage: &Storage, table: &str) {
+        storage.connection().execute_batch(&format!(
+            "CREATE TABLE {table} (timestamp INTEGER NOT NULL, open REAL, high REAL, low REAL, close REAL, volume REAL);
+             INSERT INTO {table} VALUES (3600, 1.0, 1.2, 0.9, 1.1, 10.0);
+             INSERT INTO {table} VALUES (7200, 1.1, 1.3, 1.0, 1.2, 20.0);
+             INSERT INTO {table} VALUES (7200, 1.1, 1.3, 1.0, 1.25, 25.0);
+             INSERT INTO {table} VALUES (3600, 1.0, 1.2, 0.9, 1.15, 15.0);"
+        )).unwrap();
+    }
+
+    fn closes(storage: &Storage, series: &SeriesKey) -> Vec<(i64, Decimal)> {
+        storage.candles(series, None, None).unwrap().into_iter().map(|c| (c.timestamp.timestamp(), c.close)).collect()
+    }
+
+    #[test]
+    fn unique_timestamp_migration_keeps_the_newest_duplicate_once() {
+        let mut storage = Storage::open(":memory:").unwrap();
+        let series = SeriesKey::new("eur_usd", Granularity::H1, PriceComponent::M);
+        legacy_table(&storage, &series.name());
+
+        assert_eq!(storage.setup_series(&series).unwrap(), 2);
+        assert_eq!(closes(&storage, &series), [(3600, price("1.15")), (7200, price("1.25"))]);
+        assert_eq!(storage.setup_series(&series).unwrap(), 0);
+        let indexes: usize = storage.connection().query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'eur_usd_H1'",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(indexes, 1);
+    }
+
+    #[test]
+    fn normalize_moves_legacy_tables_into_candles_once() {
+        let mut storage = Storage::open(":memory:").unwrap();
+        let mid = SeriesKey::new("eur_usd", Granularity::H1, PriceComponent::M);
+        let bid = SeriesKey::new("xau_usd", Granularity::D, PriceComponent::B);
+        legacy_table(&storage, &mid.name());
+        legacy_table(&storage, &bid.name());
+        storage.setup_series(&bid).unwrap();
+
+        assert_eq!(storage.normalize().unwrap(), 2);
+        assert_eq!(storage.layout(), Layout::Normalized);
+        assert!(!storage.table_exists(&mid.name()).unwrap());
+        assert!(!storage.table_exists(&bid.name()).unwrap());
+        for series in [&mid, &bid] {
+            assert_eq!(closes(&storage, series), [(3600, price("1.15")), (7200, price("1.25"))]);
+        }
+
+        assert_eq!(storage.normalize().unwrap(), 0);
+        let rows: usize = storage.connection().query_row("SELECT COUNT(*) FROM candles", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 4);
+    }
+
+    #[test]
+    fn table_names_split_into_instrument_period_and_component() {
+        assert_eq!(split_table_name("eur_usd_H1"), Some(("eur_usd", "H1", PriceComponent::M)));
+        assert_eq!(split_table_name("spx500_usd_D_bid"), Some(("spx500_usd", "D", PriceComponent::B)));
+        assert_eq!(split_table_name("xau_usd_2D_ask"), Some(("xau_usd", "2D", PriceComponent::A)));
+        assert_eq!(split_table_name("candles"), None);
+        assert_eq!(split_table_name("EUR_USD_H1"), None);
+        assert_eq!(split_table_name("_H1"), None);
+    }
+
+    #[test]
+    fn series_and_resampled_keys_parse_their_names() {
+        let series = SeriesKey::new("eur_usd", Granularity::M, PriceComponent::A);
+        assert_eq!(series.name(), "eur_usd_M_ask");
+        assert_eq!(SeriesKey::parse(&series.name()), Some(series));
+
+        for timeframe in [Timeframe::Hours(4), Timeframe::Days(2), Timeframe::Months(2), Timeframe::Months(3), Timeframe::Months(12)] {
+            let key = ResampledKey::new("xau_usd", timeframe, PriceComponent::B);
+            assert_eq!(ResampledKey::parse(&key.name()), Some(key));
+        }
+        assert_eq!(ResampledKey::new("eur_usd", Timeframe::Months(3), PriceComponent::M).name(), "eur_usd_Q");
+        // Granularities and non-canonical spellings are not resampled series.
+        assert_eq!(ResampledKey::parse("eur_usd_H4"), None);
+        assert_eq!(ResampledKey::parse("eur_usd_3M"), None);
+        assert_eq!(ResampledKey::parse("eur_usd_4h"), None);
+    }
+}
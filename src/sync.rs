@@ -0,0 +1,514 @@
+use crate::candle::{Candle, Granularity, PriceComponent};
+use crate::client::{CandleQuery, OandaClient, MAX_CANDLES_PER_REQUEST};
+use crate::error::{Error, Result};
+use crate::schedule::Alignment;
+use crate::storage::{Retention, SeriesKey, Storage};
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::stream::{self, StreamExt};
+use std::collections::HashSet;
+use tokio::sync::mpsc;
+
+const CANDLES_PER_REQUEST: usize = 500;
+
+/// One instrument/granularity to bring up to date.
+#[derive(Debug, Clone)]
+pub struct SeriesSpec {
+    pub instrument: String,
+    pub granularity: Granularity,
+    pub components: Vec<PriceComponent>,
+    pub retention: Retention,
+    /// Also walk back from the earliest stored candle, down to `backfill_from` or the
+    /// instrument's inception.
+    pub backfill: bool,
+    pub backfill_from: Option<DateTime<Utc>>,
+    /// Also keep the forming candle, see [`Storage::store_live`].
+    pub live: bool,
+    /// Where days and weeks start, see [`Storage::check_alignment`].
+    pub alignment: Alignment,
+}
+
+/// Progress reported by [`sync`].
+#[derive(Debug)]
+pub enum SyncEvent<'a> {
+    /// Duplicates left behind by older versions were removed from a series' table.
+    Deduplicated { series: &'a SeriesKey, removed: usize },
+    /// A page of candles was written to a series.
+    Stored { series: &'a SeriesKey, candles: usize, backfill: bool },
+    /// A series failed; no further pages are written for it.
+    Failed { instrument: &'a str, granularity: Granularity, error: &'a Error },
+}
+
+/// Everything the fetchers need to know about one series. It is read from the
+/// database up front so that fetching never touches the connection.
+struct SeriesPlan<'a> {
+    spec: &'a SeriesSpec,
+    /// Every component is fetched in the same request, so the stored series of one
+    /// spec share a single `from`/`to` cursor.
+    stored: Vec<SeriesKey>,
+    /// Where the forward sync resumes: the stored series that is furthest behind, as
+    /// upserts absorb the overlap.
+    latest: Option<DateTime<Utc>>,
+    backfill: Option<Backfill>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Backfill {
+    /// Where the walk back begins. An empty series has to be filled from the newest candle down.
+    earliest: Option<DateTime<Utc>>,
+    start: Option<DateTime<Utc>>,
+    /// How many older candles a bar-count retention still has room for.
+    limit: Option<usize>,
+}
+
+fn plan_series<'a>(storage: &mut Storage, spec: &'a SeriesSpec, on_event: &mut impl FnMut(SyncEvent)) -> Result<SeriesPlan<'a>> {
+    for series in &spec_series(spec) {
+        let removed = storage.setup_series(series)?;
+        if removed > 0 {
+            on_event(SyncEvent::Deduplicated { series, removed });
+        }
+        storage.check_alignment(series, spec.alignment)?;
+    }
+    read_plan(storage, spec)
+}
+
+fn spec_series(spec: &SeriesSpec) -> Vec<SeriesKey> {
+    spec.components.iter()
+        .map(|&c| SeriesKey::new(&spec.instrument, spec.granularity, c))
+        .collect()
+}
+
+/// The part of planning that only reads the database.
+fn read_plan<'a>(storage: &Storage, spec: &'a SeriesSpec) -> Result<SeriesPlan<'a>> {
+    let stored = spec_series(spec);
+    let latest = stored.iter().map(|s| storage.last_timestamp(s)).collect::<Result<Vec<_>>>()?;
+    let earliest = stored.iter().map(|s| storage.first_timestamp(s)).collect::<Result<Vec<_>>>()?;
+
+    // Anything older than the retention keeps would be pruned right away.
+    let limit = match spec.retention {
+        Retention::Bars(bars) => {
+            let stored = stored.iter().map(|s| Ok(storage.stats(s)?.rows)).collect::<Result<Vec<_>>>()?;
+            Some(bars.saturating_sub(stored.iter().copied().min().unwrap_or(0)))
+        }
+        _ => None,
+    };
+    let backfill = (spec.backfill && limit != Some(0)).then(|| Backfill {
+        earliest: if earliest.contains(&None) { None } else { earliest.iter().copied().max().flatten() },
+        start: match spec.retention {
+            Retention::Window(window) => spec.backfill_from.max(Some(Utc::now() - window)),
+            _ => spec.backfill_from,
+        },
+        limit,
+    });
+
+    Ok(SeriesPlan {
+        spec,
+        stored,
+        latest: latest.iter().copied().min().flatten(),
+        backfill,
+    })
+}
+
+/// What [`sync`] would fetch for one stored series, as worked out by [`plan`].
+#[derive(Debug, Clone)]
+pub struct PlannedSeries {
+    pub series: SeriesKey,
+    /// Where fetching forward resumes, shared by the series of one [`SeriesSpec`]. `None`
+    /// if one of them is empty, in which case the newest page is fetched.
+    pub from: Option<DateTime<Utc>>,
+    /// Estimated number of candles fetched forward, see [`Alignment::count_candles`].
+    pub candles: usize,
+    pub backfill: Option<PlannedBackfill>,
+}
+
+/// The history a backfill walks back through.
+#[derive(Debug, Clone, Copy)]
+pub struct PlannedBackfill {
+    /// Oldest candle wanted, `None` for the instrument's inception.
+    pub from: Option<DateTime<Utc>>,
+    /// Earliest stored candle, `None` if a series is empty and the walk starts at the newest.
+    pub to: Option<DateTime<Utc>>,
+    /// Estimated number of candles, unknown without `from`.
+    pub candles: Option<usize>,
+}
+
+/// Works out what [`sync`] would fetch for the series of `spec` as of `now`, without
+/// writing to `storage`. Fails where `sync` would fail before fetching, e.g. for a
+/// series stored with another alignment.
+pub fn plan(storage: &Storage, spec: &SeriesSpec, now: DateTime<Utc>) -> Result<Vec<PlannedSeries>> {
+    for series in &spec_series(spec) {
+        storage.compatible_alignment(series, spec.alignment)?;
+    }
+    let plan = read_plan(storage, spec)?;
+
+    let candles = match plan.latest {
+        Some(latest) => spec.alignment.count_candles(spec.granularity, latest, now),
+        // Without `from`, OANDA returns a single page ending with the forming candle.
+        None => CANDLES_PER_REQUEST - 1,
+    };
+    let backfill = plan.backfill.map(|Backfill { earliest, start, limit }| PlannedBackfill {
+        from: start,
+        to: earliest,
+        candles: match start.map(|start| {
+            spec.alignment.count_candles(spec.granularity, start - TimeDelta::seconds(1), earliest.unwrap_or(now))
+        }) {
+            Some(candles) => Some(limit.map_or(candles, |limit| candles.min(limit))),
+            None => limit,
+        },
+    });
+    Ok(plan.stored
+        .into_iter()
+        .map(|series| PlannedSeries { series, from: plan.latest, candles, backfill })
+        .collect())
+}
+
+/// Messages from the concurrent fetchers to the single database writer.
+enum Fetched {
+    Page { series: usize, candles: Vec<Candle>, backfill: bool },
+    Live { series: usize, candle: Option<Box<Candle>> },
+    Failed { series: usize, error: Error },
+}
+
+async fn fetch_series(client: &OandaClient, series: usize, plan: &SeriesPlan<'_>, tx: &mpsc::Sender<Fetched>) -> Result<()> {
+    let spec = plan.spec;
+
+    if let Some(backfill) = plan.backfill {
+        backfill_series(client, series, spec, backfill, tx).await?;
+    }
+
+    let mut last_timestamp = plan.latest;
+
+    // Short granularities easily fall more than one page behind between runs,
+    // so keep paging forward until OANDA returns a partial page. Without `from`
+    // the page already ends at the newest candle.
+    loop {
+        let query = CandleQuery { from: last_timestamp, to: None, count: CANDLES_PER_REQUEST, alignment: spec.alignment };
+        let candles = client.candles(&spec.instrument, spec.granularity, &spec.components, query).await?;
+        let fetched = candles.len();
+
+        let (complete, mut forming): (Vec<Candle>, Vec<Candle>) = candles.into_iter().partition(|c| c.complete);
+        let new_candles: Vec<Candle> = complete
+            .into_iter()
+            .filter(|c| last_timestamp.is_none_or(|ts| c.time > ts))
+            .collect();
+
+        let next = match new_candles.last() {
+            Some(last) if query.from.is_some() && fetched >= CANDLES_PER_REQUEST => Some(last.time),
+            _ => None,
+        };
+
+        let _ = tx.send(Fetched::Page { series, candles: new_candles, backfill: false }).await;
+
+        match next {
+            Some(time) => last_timestamp = Some(time),
+            None => {
+                // Only the newest candle can still be forming, and it is on the last page.
+                if spec.live {
+                    let _ = tx.send(Fetched::Live { series, candle: forming.pop().map(Box::new) }).await;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Walks backwards from the earliest stored candle in pages of `MAX_CANDLES_PER_REQUEST`
+/// until `start` is reached, `limit` candles are stored or OANDA has nothing older (the
+/// instrument's inception).
+async fn backfill_series(client: &OandaClient, series: usize, spec: &SeriesSpec, backfill: Backfill, tx: &mpsc::Sender<Fetched>) -> Result<()> {
+    let Backfill { mut earliest, start, mut limit } = backfill;
+
+    loop {
+        if let (Some(start), Some(earliest)) = (start, earliest)
+            && earliest <= start
+        {
+            return Ok(());
+        }
+
+        // The candle at `to` comes back too and is dropped below.
+        let count = limit.map_or(MAX_CANDLES_PER_REQUEST, |limit| (limit + 1).min(MAX_CANDLES_PER_REQUEST));
+        let query = CandleQuery { from: None, to: earliest, count, alignment: spec.alignment };
+        let candles = client.candles(&spec.instrument, spec.granularity, &spec.components, query).await?;
+
+        let old_candles: Vec<Candle> = candles
+            .into_iter()
+            .filter(|c| c.complete && earliest.is_none_or(|ts| c.time < ts))
+            .collect();
+
+        let Some(first) = old_candles.first() else {
+            return Ok(());
+        };
+        earliest = Some(first.time);
+
+        let mut old_candles: Vec<Candle> = old_candles
+            .into_iter()
+            .filter(|c| start.is_none_or(|ts| c.time >= ts))
+            .collect();
+        if let Some(limit) = &mut limit {
+            old_candles.drain(..old_candles.len().saturating_sub(*limit));
+            *limit -= old_candles.len();
+        }
+
+        let _ = tx.send(Fetched::Page { series, candles: old_candles, backfill: true }).await;
+        if limit == Some(0) {
+            return Ok(());
+        }
+    }
+}
+
+fn write_page(storage: &mut Storage, plan: &SeriesPlan, candles: &[Candle], backfill: bool, on_event: &mut impl FnMut(SyncEvent)) -> Result<()> {
+    for series in &plan.stored {
+        storage.insert_candles(series, candles, plan.spec.retention)?;
+        on_event(SyncEvent::Stored { series, candles: candles.len(), backfill });
+    }
+    Ok(())
+}
+
+fn write_live(storage: &mut Storage, plan: &SeriesPlan, candle: Option<&Candle>) -> Result<()> {
+    for series in &plan.stored {
+        storage.store_live(series, candle)?;
+    }
+    Ok(())
+}
+
+/// Brings every series up to date and returns how many of them failed.
+///
+/// Up to `concurrency` series are fetched at once while all writes go through
+/// `storage` from the calling task, so pages of one series are stored in the order
+/// they were fetched. A failing series is reported through `on_event` and does not
+/// stop the others.
+pub async fn sync(client: &OandaClient, storage: &mut Storage, series: &[SeriesSpec], concurrency: usize, mut on_event: impl FnMut(SyncEvent)) -> usize {
+    let mut failed = 0;
+    let mut plans = Vec::new();
+
+    for spec in series {
+        match plan_series(storage, spec, &mut on_event) {
+            Ok(plan) => plans.push(plan),
+            Err(error) => {
+                on_event(SyncEvent::Failed { instrument: &spec.instrument, granularity: spec.granularity, error: &error });
+                failed += 1;
+            }
+        }
+    }
+
+    let concurrency = concurrency.max(1);
+    let (tx, mut rx) = mpsc::channel(concurrency * 2);
+    let plans = &plans;
+    let fetching = async move {
+        stream::iter(plans.iter().enumerate())
+            .for_each_concurrent(concurrency, |(series, plan)| {
+                let tx = tx.clone();
+                async move {
+                    if let Err(error) = fetch_series(client, series, plan, &tx).await {
+                        let _ = tx.send(Fetched::Failed { series, error }).await;
+                    }
+                }
+            })
+            .await;
+    };
+    let writing = async {
+        let mut series_failed = HashSet::new();
+        while let Some(fetched) = rx.recv().await {
+            let (series, result) = match fetched {
+                Fetched::Page { series, candles, backfill } if !series_failed.contains(&series) => {
+                    (series, write_page(storage, &plans[series], &candles, backfill, &mut on_event))
+                }
+                Fetched::Live { series, candle } if !series_failed.contains(&series) => {
+                    (series, write_live(storage, &plans[series], candle.as_deref()))
+                }
+                Fetched::Page { .. } | Fetched::Live { .. } => continue,
+                Fetched::Failed { series, error } => (series, Err(error)),
+            };
+            if let Err(error) = result && series_failed.insert(series) {
+                let spec = plans[series].spec;
+                on_event(SyncEvent::Failed { instrument: &spec.instrument, granularity: spec.granularity, error: &error });
+            }
+        }
+        series_failed.len()
+    };
+    let ((), series_failed) = tokio::join!(fetching, writing);
+
+    failed + series_failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{candle, ohlc, query, serve, storage_with, store, utc};
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Hourly candles of EUR_USD and USD_JPY from the first one on, the last one still
+    /// forming. Other instruments are refused like OANDA refuses unknown names.
+    struct Market {
+        hours: Vec<DateTime<Utc>>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl Market {
+        fn new(hours: i64) -> Arc<Market> {
+            let first = utc("2026-01-05T00:00:00Z");
+            Arc::new(Market { hours: (0..hours).map(|n| first + TimeDelta::hours(n)).collect(), requests: Mutex::new(Vec::new()) })
+        }
+
+        fn hour(&self, n: usize) -> DateTime<Utc> {
+            self.hours[n]
+        }
+
+        fn respond(&self, target: &str) -> (u16, String) {
+            self.requests.lock().unwrap().push(target.to_string());
+            if !target.starts_with("/instruments/EUR_USD/") && !target.starts_with("/instruments/USD_JPY/") {
+                return (400, r#"{"errorMessage": "Invalid value specified for 'instrument'"}"#.to_string());
+            }
+
+            let time = |name| query(target, name).map(|t| DateTime::from_timestamp(t.parse().unwrap(), 0).unwrap());
+            let (from, to) = (time("from"), time("to"));
+            let count: usize = query(target, "count").map_or(usize::MAX, |c| c.parse().unwrap());
+            let window: Vec<&DateTime<Utc>> = self.hours.iter()
+                .filter(|&&t| from.is_none_or(|from| t >= from) && to.is_none_or(|to| t <= to))
+                .collect();
+            let page = match from {
+                Some(_) => &window[..count.min(window.len())],
+                None => &window[window.len().saturating_sub(count)..],
+            };
+
+            let forming = self.hours.last();
+            let candles: Vec<serde_json::Value> = page.iter()
+                .map(|&t| serde_json::json!({
+                    "time": t.to_rfc3339(),
+                    "complete": Some(t) != forming,
+                    "volume": 1,
+                    "mid": {"o": "1.1", "h": "1.2", "l": "1.0", "c": "1.1"},
+                }))
+                .collect();
+            (200, serde_json::json!({ "candles": candles }).to_string())
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    async fn client(market: &Arc<Market>) -> OandaClient {
+        let market = market.clone();
+        let url = serve(move |target| market.respond(target)).await;
+        OandaClient::builder(url, "token").retries(0).max_requests_per_second(10_000).build().unwrap()
+    }
+
+    fn spec(instrument: &str, retention: Retention, backfill: bool, backfill_from: Option<DateTime<Utc>>) -> SeriesSpec {
+        SeriesSpec {
+            instrument: instrument.to_string(),
+            granularity: Granularity::H1,
+            components: vec![PriceComponent::M],
+            retention,
+            backfill,
+            backfill_from,
+            live: false,
+            alignment: Alignment::default(),
+        }
+    }
+
+    fn series(instrument: &str) -> SeriesKey {
+        SeriesKey::new(instrument, Granularity::H1, PriceComponent::M)
+    }
+
+    /// The market's candles `hours`.
+    fn candles(market: &Market, hours: std::ops::Range<usize>) -> Vec<Candle> {
+        hours.map(|n| candle(market.hour(n), ohlc("1.1", "1.2", "1.0", "1.1"))).collect()
+    }
+
+    #[tokio::test]
+    async fn syncing_pages_forward_until_a_partial_page() {
+        let market = Market::new(1201);
+        let client = client(&market).await;
+        let mut storage = storage_with(&series("EUR_USD"), &candles(&market, 0..1));
+
+        let failed = sync(&client, &mut storage, &[spec("EUR_USD", Retention::All, false, None)], 1, |_| {}).await;
+        assert_eq!(failed, 0);
+
+        let stats = storage.stats(&series("EUR_USD")).unwrap();
+        // Everything but the forming candle.
+        assert_eq!((stats.rows, stats.last), (1200, Some(market.hour(1199))));
+        let requests = market.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| query(r, "count") == Some("500")));
+    }
+
+    #[tokio::test]
+    async fn backfilling_stops_at_the_inception() {
+        let market = Market::new(6001);
+        let client = client(&market).await;
+        let mut storage = Storage::open(":memory:").unwrap();
+
+        assert_eq!(sync(&client, &mut storage, &[spec("EUR_USD", Retention::All, true, None)], 1, |_| {}).await, 0);
+
+        let stats = storage.stats(&series("EUR_USD")).unwrap();
+        assert_eq!((stats.rows, stats.first), (6000, Some(market.hour(0))));
+        // Two pages, the empty one before the first candle and the forward sync.
+        assert_eq!(market.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn backfilling_stops_at_the_start_date() {
+        let market = Market::new(6001);
+        let client = client(&market).await;
+        let mut storage = Storage::open(":memory:").unwrap();
+
+        let spec = spec("EUR_USD", Retention::All, true, Some(market.hour(3000)));
+        assert_eq!(sync(&client, &mut storage, &[spec], 1, |_| {}).await, 0);
+
+        let stats = storage.stats(&series("EUR_USD")).unwrap();
+        assert_eq!((stats.rows, stats.first), (3000, Some(market.hour(3000))));
+        // The first page already reaches past the start date.
+        assert_eq!(market.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn backfilling_stops_once_a_bar_count_retention_is_full() {
+        let market = Market::new(6001);
+        let client = client(&market).await;
+        let mut storage = storage_with(&series("EUR_USD"), &candles(&market, 5960..6000));
+
+        assert_eq!(sync(&client, &mut storage, &[spec("EUR_USD", Retention::Bars(100), true, None)], 1, |_| {}).await, 0);
+
+        let stats = storage.stats(&series("EUR_USD")).unwrap();
+        assert_eq!((stats.rows, stats.first), (100, Some(market.hour(5900))));
+        let requests = market.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(query(&requests[0], "count"), Some("61"));
+
+        // Once full, nothing older is fetched.
+        sync(&client, &mut storage, &[spec("EUR_USD", Retention::Bars(100), true, None)], 1, |_| {}).await;
+        assert_eq!(market.requests().len(), 3);
+        assert!(query(&market.requests()[2], "from").is_some());
+    }
+
+    #[tokio::test]
+    async fn a_failing_series_leaves_the_others_stored() {
+        let market = Market::new(1201);
+        let client = client(&market).await;
+        let mut storage = storage_with(&series("EUR_USD"), &candles(&market, 0..1));
+        store(&mut storage, &series("USD_JPY"), &candles(&market, 0..1));
+
+        let specs = [
+            spec("EUR_USD", Retention::All, false, None),
+            spec("XXX_USD", Retention::All, false, None),
+            spec("USD_JPY", Retention::All, false, None),
+        ];
+        let mut stored_candles: BTreeMap<String, usize> = BTreeMap::new();
+        let mut failures = Vec::new();
+        let failed = sync(&client, &mut storage, &specs, 3, |event| match event {
+            SyncEvent::Stored { series, candles, .. } => *stored_candles.entry(series.name()).or_default() += candles,
+            SyncEvent::Failed { instrument, .. } => failures.push(instrument.to_string()),
+            SyncEvent::Deduplicated { .. } => {}
+        })
+        .await;
+
+        assert_eq!(failed, 1);
+        assert_eq!(failures, ["XXX_USD"]);
+        for name in ["EUR_USD", "USD_JPY"] {
+            // Every page the fetchers sent was written.
+            assert_eq!(storage.stats(&series(name)).unwrap().rows, 1200);
+            assert_eq!(stored_candles[&series(name).name()], 1199);
+        }
+    }
+}
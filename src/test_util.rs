@@ -0,0 +1,88 @@
+//! Helpers shared by the unit tests.
+
+use crate::candle::{Candle, Ohlc};
+use crate::client::Instrument;
+use crate::storage::{Retention, SeriesKey, Storage};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+pub fn utc(s: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(s).expect("valid test time").with_timezone(&Utc)
+}
+
+pub fn price(s: &str) -> Decimal {
+    s.parse().expect("valid test price")
+}
+
+pub fn ohlc(o: &str, h: &str, l: &str, c: &str) -> Ohlc {
+    Ohlc { o: price(o), h: price(h), l: price(l), c: price(c) }
+}
+
+/// A complete candle with mid prices `mid` and a volume of one.
+pub fn candle(time: DateTime<Utc>, mid: Ohlc) -> Candle {
+    Candle { time, complete: true, volume: 1.0, mid: Some(mid), bid: None, ask: None }
+}
+
+/// A currency pair quoted to five decimals, as OANDA lists EUR_USD.
+pub fn instrument(name: &str) -> Instrument {
+    serde_json::from_value(serde_json::json!({
+        "name": name, "type": "CURRENCY", "displayName": name.replace('_', "/"), "pipLocation": -4, "displayPrecision": 5,
+    }))
+    .expect("valid test instrument")
+}
+
+/// Sets up `series` and stores `candles` in it, keeping everything.
+pub fn store(storage: &mut Storage, series: &SeriesKey, candles: &[Candle]) {
+    storage.setup_series(series).unwrap();
+    storage.insert_candles(series, candles, Retention::All).unwrap();
+}
+
+/// An in-memory database holding `candles` in `series`.
+pub fn storage_with(series: &SeriesKey, candles: &[Candle]) -> Storage {
+    let mut storage = Storage::open(":memory:").unwrap();
+    store(&mut storage, series, candles);
+    storage
+}
+
+/// Stands in for OANDA on a local port: every request is answered with the status and
+/// JSON body `respond` returns for its path and query. Returns the base URL.
+pub async fn serve(respond: impl Fn(&str) -> (u16, String) + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let respond = Arc::new(respond);
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let (status, body) = respond(request.split_whitespace().nth(1).unwrap_or("/"));
+                let response = format!(
+                    "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    url
+}
+
+/// Value of the query parameter `name` in a request target.
+pub fn query<'a>(target: &'a str, name: &str) -> Option<&'a str> {
+    target.split_once('?')?.1
+        .split('&')
+        .find_map(|pair| pair.split_once('=').filter(|(key, _)| *key == name).map(|(_, value)| value))
+}
@@ -0,0 +1,172 @@
+use crate::candle::{Candle, Granularity};
+use crate::client::{CandleQuery, OandaClient, MAX_CANDLES_PER_REQUEST};
+use crate::error::Result;
+use crate::schedule::Alignment;
+use crate::storage::{Retention, SeriesKey, Storage};
+use chrono::{DateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::America::New_York;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// A run of candles missing from a stored series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Gap {
+    /// Open of the first missing candle.
+    pub from: DateTime<Utc>,
+    /// Close of the last missing candle.
+    pub to: DateTime<Utc>,
+    pub missing: usize,
+}
+
+/// What [`repair`] did about one gap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Repaired {
+    pub fetched: usize,
+    /// Ranges OANDA has no candles for, now recorded as closures.
+    pub closures: usize,
+}
+
+/// Finds the candles missing between the first and last stored candle of `series`.
+///
+/// Candles are expected at every boundary of `alignment` except over the weekend.
+/// Once a series shorter than daily covers a week, times of day at which none of its
+/// candles open, in UTC or New York time, are taken for the daily break of an
+/// instrument that doesn't trade around the clock and not reported. Holidays are,
+/// until [`repair`] has recorded them as closures along with any other range OANDA
+/// has no candles for.
+pub fn find_gaps(storage: &Storage, series: &SeriesKey, alignment: Alignment) -> Result<Vec<Gap>> {
+    let stored = storage.timestamps(series)?;
+    let closures = storage.closures(&series.instrument, series.granularity)?;
+    let in_break = daily_break(series.granularity, &stored);
+
+    let mut gaps = Vec::new();
+    for pair in stored.windows(2) {
+        let mut current: Option<Gap> = None;
+        let start = alignment.next_close(series.granularity, pair[0]);
+        for (open, close) in expected(alignment, series.granularity, start, pair[1]) {
+            if is_closed(&closures, open) || in_break(open) {
+                gaps.extend(current.take());
+            } else {
+                let gap = current.get_or_insert(Gap { from: open, to: close, missing: 0 });
+                gap.to = close;
+                gap.missing += 1;
+            }
+        }
+        gaps.extend(current);
+    }
+    Ok(gaps)
+}
+
+/// Fetches the candles of `gap` and stores them. Whatever part of the gap OANDA has no
+/// candles for is recorded as a closure, so [`find_gaps`] stops reporting it.
+pub async fn repair(client: &OandaClient, storage: &mut Storage, series: &SeriesKey, alignment: Alignment, gap: Gap) -> Result<Repaired> {
+    let expected = expected(alignment, series.granularity, gap.from, gap.to);
+
+    // Request only the gap, in windows OANDA returns whole. One candle of headroom is
+    // left in case the candle at `to` comes back as well.
+    let mut candles: Vec<Candle> = Vec::new();
+    for window in expected.chunks(MAX_CANDLES_PER_REQUEST - 1) {
+        let (from, to) = (window[0].0, window[window.len() - 1].1);
+        let query = CandleQuery { from: Some(from), to: Some(to), count: window.len(), alignment };
+        let page = client.candles(&series.instrument, series.granularity, &[series.component], query).await?;
+        candles.extend(page.into_iter().filter(|c| c.complete && c.time >= from && c.time < to));
+    }
+
+    let mut closures: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for &(open, close) in &expected {
+        if candles.iter().any(|c| c.time == open) {
+            continue;
+        }
+        match closures.last_mut() {
+            Some(range) if range.1 == open => range.1 = close,
+            _ => closures.push((open, close)),
+        }
+    }
+
+    storage.insert_candles(series, &candles, Retention::All)?;
+    storage.record_closures(&series.instrument, series.granularity, &closures)?;
+    Ok(Repaired { fetched: candles.len(), closures: closures.len() })
+}
+
+/// Open and close of every candle expected to open in `[from, to)`.
+fn expected(alignment: Alignment, granularity: Granularity, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut candles = Vec::new();
+    let mut at = from;
+    loop {
+        let (open, close) = alignment.candle(granularity, at);
+        if open >= to {
+            return candles;
+        }
+        if open >= from {
+            candles.push((open, close));
+        }
+        at = close;
+    }
+}
+
+/// Whether a candle of `granularity` opening at a given time falls in the daily break
+/// the `stored` open times show, if they span a week.
+fn daily_break(granularity: Granularity, stored: &[DateTime<Utc>]) -> impl Fn(DateTime<Utc>) -> bool + use<> {
+    let intraday = granularity.duration().is_some_and(|length| length < TimeDelta::days(1));
+    let week = matches!((stored.first(), stored.last()), (Some(&first), Some(&last)) if last - first >= TimeDelta::weeks(1));
+    let (utc, new_york): (HashSet<NaiveTime>, HashSet<NaiveTime>) = if intraday && week {
+        stored.iter().map(|t| (t.time(), t.with_timezone(&New_York).time())).unzip()
+    } else {
+        Default::default()
+    };
+    move |open| !utc.is_empty() && (!utc.contains(&open.time()) || !new_york.contains(&open.with_timezone(&New_York).time()))
+}
+
+/// Whether `time` falls in one of the sorted, non-overlapping `closures`.
+fn is_closed(closures: &[(DateTime<Utc>, DateTime<Utc>)], time: DateTime<Utc>) -> bool {
+    let index = closures.partition_point(|&(start, _)| start <= time);
+    index > 0 && time < closures[index - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candle::PriceComponent;
+    use crate::test_util::{candle, ohlc, storage_with, utc};
+
+    /// H1 candles from `from` to `to` with a daily break at 21:00 UTC, leaving out `missing`.
+    fn hours(from: &str, to: &str, missing: &[&str]) -> Vec<Candle> {
+        let missing: Vec<DateTime<Utc>> = missing.iter().map(|t| utc(t)).collect();
+        expected(Alignment::default(), Granularity::H1, utc(from), utc(to))
+            .into_iter()
+            .filter(|&(open, _)| open.format("%H").to_string() != "21" && !missing.contains(&open))
+            .map(|(time, _)| candle(time, ohlc("1.1", "1.2", "1.0", "1.1")))
+            .collect()
+    }
+
+    #[test]
+    fn gaps_skip_the_weekend_and_daily_break() {
+        let series = SeriesKey::new("XAU_USD", Granularity::H1, PriceComponent::M);
+        let missing = ["2026-10-08T10:00:00Z", "2026-10-08T11:00:00Z"];
+        let storage = storage_with(&series, &hours("2026-10-05T00:00:00Z", "2026-10-14T00:00:00Z", &missing));
+
+        assert_eq!(
+            find_gaps(&storage, &series, Alignment::default()).unwrap(),
+            [Gap { from: utc("2026-10-08T10:00:00Z"), to: utc("2026-10-08T12:00:00Z"), missing: 2 }]
+        );
+    }
+
+    #[test]
+    fn daily_breaks_need_a_week_to_show() {
+        let series = SeriesKey::new("XAU_USD", Granularity::H1, PriceComponent::M);
+        let storage = storage_with(&series, &hours("2026-10-05T00:00:00Z", "2026-10-08T00:00:00Z", &[]));
+
+        let gaps = find_gaps(&storage, &series, Alignment::default()).unwrap();
+        let breaks = ["2026-10-05T21:00:00Z", "2026-10-06T21:00:00Z", "2026-10-07T21:00:00Z"];
+        assert_eq!(gaps.iter().map(|gap| gap.from).collect::<Vec<_>>(), breaks.map(utc));
+    }
+
+    #[test]
+    fn closures_are_not_reported_again() {
+        let series = SeriesKey::new("XAU_USD", Granularity::H1, PriceComponent::M);
+        let mut storage = storage_with(&series, &hours("2026-10-05T00:00:00Z", "2026-10-14T00:00:00Z", &["2026-10-08T10:00:00Z"]));
+        storage.record_closures("XAU_USD", Granularity::H1, &[(utc("2026-10-08T10:00:00Z"), utc("2026-10-08T11:00:00Z"))]).unwrap();
+
+        assert!(find_gaps(&storage, &series, Alignment::default()).unwrap().is_empty());
+    }
+}
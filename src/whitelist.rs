@@ -0,0 +1,131 @@
+use crate::client::Instrument;
+use crate::error::{Error, Result};
+use regex::{Regex, RegexBuilder};
+
+/// How whitelist entries are compared with instrument names. Matching is always
+/// case-insensitive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MatchMode {
+    /// The entry is the full instrument name, e.g. `eur_usd`.
+    #[default]
+    Exact,
+    /// `*` and `?` wildcards over the full name, e.g. `xau_*`.
+    Glob,
+    /// A regular expression found anywhere in the name, e.g. `^xau_`.
+    Regex,
+}
+
+/// Instruments to sync, selected from what the account offers.
+#[derive(Debug, Clone)]
+pub struct Whitelist {
+    entries: Vec<(String, Regex)>,
+}
+
+impl Whitelist {
+    pub fn new<S: AsRef<str>>(entries: &[S], mode: MatchMode) -> Result<Self> {
+        let entries = entries.iter()
+            .map(|entry| {
+                let entry = entry.as_ref().trim();
+                let pattern = match mode {
+                    MatchMode::Exact => format!("^{}$", regex::escape(entry)),
+                    MatchMode::Glob => format!("^{}$", glob_to_regex(entry)),
+                    MatchMode::Regex => entry.to_string(),
+                };
+                let regex = RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| Error::Config(format!("invalid pattern `{}`: {}", entry, e)))?;
+                Ok((entry.to_string(), regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Whitelist { entries })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.entries.iter().any(|(_, regex)| regex.is_match(name))
+    }
+
+    /// Entries that match none of `instruments`.
+    pub fn unmatched(&self, instruments: &[Instrument]) -> Vec<String> {
+        self.entries.iter()
+            .filter(|(_, regex)| !instruments.iter().any(|i| regex.is_match(&i.name)))
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+
+    /// Instruments matching any entry, in account order. Fails with
+    /// `Error::UnmatchedWhitelist` if an entry matched nothing, so typos don't go unnoticed.
+    pub fn select<'a>(&self, instruments: &'a [Instrument]) -> Result<Vec<&'a Instrument>> {
+        let unmatched = self.unmatched(instruments);
+        if !unmatched.is_empty() {
+            return Err(Error::UnmatchedWhitelist(unmatched));
+        }
+
+        Ok(instruments.iter().filter(|i| self.matches(&i.name)).collect())
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::instrument;
+
+    fn names(instruments: &[&Instrument]) -> Vec<String> {
+        instruments.iter().map(|i| i.name.clone()).collect()
+    }
+
+    #[test]
+    fn exact_entries_match_whole_names_in_any_case() {
+        let whitelist = Whitelist::new(&["eur_usd", " xau "], MatchMode::Exact).unwrap();
+        assert!(whitelist.matches("EUR_USD"));
+        assert!(!whitelist.matches("EUR_USDX"));
+        assert!(!whitelist.matches("XAU_USD"));
+        assert!(!Whitelist::new(&["eur.usd"], MatchMode::Exact).unwrap().matches("EUR_USD"));
+    }
+
+    #[test]
+    fn globs_are_anchored() {
+        let whitelist = Whitelist::new(&["xau_*", "?ur_usd"], MatchMode::Glob).unwrap();
+        assert!(whitelist.matches("XAU_USD"));
+        assert!(whitelist.matches("XAU_EUR"));
+        assert!(whitelist.matches("EUR_USD"));
+        assert!(!whitelist.matches("EUR_XAU"));
+        assert!(!whitelist.matches("EUR_USD_X"));
+    }
+
+    #[test]
+    fn regexes_match_anywhere_unless_anchored() {
+        let whitelist = Whitelist::new(&["usd"], MatchMode::Regex).unwrap();
+        assert!(whitelist.matches("EUR_USD"));
+        assert!(whitelist.matches("USD_JPY"));
+        let anchored = Whitelist::new(&["^xau_"], MatchMode::Regex).unwrap();
+        assert!(anchored.matches("xau_usd"));
+        assert!(!anchored.matches("EUR_XAU_USD"));
+        assert!(matches!(Whitelist::new(&["("], MatchMode::Regex), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn entries_matching_nothing_are_reported() {
+        let account = [instrument("EUR_USD"), instrument("XAU_USD"), instrument("USD_JPY")];
+
+        let whitelist = Whitelist::new(&["usd_jpy", "eur_usd"], MatchMode::Exact).unwrap();
+        assert!(whitelist.unmatched(&account).is_empty());
+        assert_eq!(names(&whitelist.select(&account).unwrap()), ["EUR_USD", "USD_JPY"]);
+
+        let whitelist = Whitelist::new(&["eur_usd", "xau", "gbp_*"], MatchMode::Glob).unwrap();
+        assert_eq!(whitelist.unmatched(&account), ["xau", "gbp_*"]);
+        assert!(matches!(whitelist.select(&account), Err(Error::UnmatchedWhitelist(e)) if e == ["xau", "gbp_*"]));
+    }
+}